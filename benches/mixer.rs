@@ -0,0 +1,131 @@
+//! Throughput of the mixer's render path with a growing number of renderers,
+//! for synthesized tones, music tracks and sound effect voices.
+//!
+//! Run with `cargo bench --bench mixer`.
+
+use anyhow::Result;
+use sasa::{
+    backend::BackendSetup, AudioClip, AudioManager, Backend, Frame, LoopMode, MusicParams,
+    Oscillator, PlaySfxParams, ToneParams, Waveform,
+};
+use std::{
+    any::Any,
+    cell::RefCell,
+    hint::black_box,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+const SAMPLE_RATE: u32 = 48000;
+const BLOCK: usize = 512;
+/// About 11 seconds of output, shorter than the clip so that no voice ends
+const BLOCKS: u32 = 1000;
+
+/// Hands the mixer to the benchmark instead of a device.
+struct BenchBackend(Rc<RefCell<Option<BackendSetup>>>);
+
+impl Backend for BenchBackend {
+    fn setup(&mut self, mut setup: BackendSetup) -> Result<()> {
+        setup.set_sample_rate(SAMPLE_RATE);
+        *self.0.borrow_mut() = Some(setup);
+        Ok(())
+    }
+
+    fn start(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn consume_broken(&self) -> bool {
+        false
+    }
+}
+
+/// A 44.1 kHz clip, so that playing it goes through the resampler.
+fn clip() -> AudioClip {
+    let frames = (0..44100 * 15)
+        .map(|i| {
+            let value = (i as f32 * 0.05).sin() * 0.5;
+            Frame(value, -value)
+        })
+        .collect();
+    AudioClip::from_raw(frames, 44100)
+}
+
+/// Renders with `count` sources added by `add`, which returns whatever has to
+/// stay alive meanwhile.
+fn bench(
+    name: &str,
+    count: usize,
+    add: impl FnOnce(&mut AudioManager) -> Result<Box<dyn Any>>,
+) -> Result<()> {
+    let setup = Rc::default();
+    let mut manager = AudioManager::builder()
+        .backend(BenchBackend(Rc::clone(&setup)))
+        .command_buffer(count + 1)
+        .build()?;
+    let _handles = add(&mut manager)?;
+
+    let mut setup = setup.borrow_mut();
+    let setup = setup.as_mut().unwrap();
+    let mut data = vec![0.; BLOCK * 2];
+    // Picks up the renderers and warms up caches
+    for _ in 0..16 {
+        setup.render(2, &mut data);
+    }
+    let start = Instant::now();
+    for _ in 0..BLOCKS {
+        setup.render(2, black_box(&mut data));
+    }
+    let per_block = start.elapsed() / BLOCKS;
+    let budget = Duration::from_secs_f64(BLOCK as f64 / SAMPLE_RATE as f64);
+    println!(
+        "{name:>11} x{count:<4} {per_block:>10.2?} per {BLOCK}-frame block, {:>6.2}% of real time",
+        per_block.as_secs_f64() / budget.as_secs_f64() * 100.
+    );
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let clip = clip();
+    for count in [1, 8, 32, 128] {
+        bench("oscillators", count, |manager| {
+            for i in 0..count {
+                let params = ToneParams {
+                    waveform: Waveform::Sawtooth,
+                    amplifier: 1. / count as f32,
+                };
+                manager.add_renderer(Oscillator::new(220. + i as f32, None, params))?;
+            }
+            Ok(Box::new(()))
+        })?;
+    }
+    for count in [1, 8, 32, 128] {
+        bench("music", count, |manager| {
+            let mut tracks = Vec::new();
+            for _ in 0..count {
+                let params = MusicParams {
+                    loop_mode: Some(LoopMode::Seamless),
+                    amplifier: 1. / count as f32,
+                    ..Default::default()
+                };
+                let mut music = manager.create_music(clip.clone(), params)?;
+                music.play()?;
+                tracks.push(music);
+            }
+            Ok(Box::new(tracks))
+        })?;
+    }
+    for count in [1, 8, 32, 128] {
+        bench("sfx voices", count, |manager| {
+            let mut sfx = manager.create_sfx(clip.clone(), Some(count))?;
+            for _ in 0..count {
+                sfx.play(PlaySfxParams {
+                    amplifier: 1. / count as f32,
+                    ..Default::default()
+                })?;
+            }
+            Ok(Box::new(sfx))
+        })?;
+    }
+    Ok(())
+}
@@ -0,0 +1,358 @@
+use crate::Frame;
+use std::f32::consts::TAU;
+
+/// Frames per second of the onset envelope.
+const ENVELOPE_RATE: f32 = 200.;
+
+/// Result of [`crate::AudioClip::estimate_bpm`].
+#[derive(Debug, Clone, Copy)]
+pub struct BpmEstimate {
+    pub bpm: f32,
+    /// How periodic the onsets are at that tempo, from 0 to 1: close to 1 for
+    /// a strict click track and lower as the rhythm loosens. Below 0.1 no
+    /// estimate is made at all.
+    pub confidence: f32,
+}
+
+/// Rises of the log energy over hops of about `1 / ENVELOPE_RATE` seconds,
+/// which peak where notes and hits start. Also returns the exact rate.
+fn onset_envelope(frames: &[Frame], sample_rate: u32) -> (Vec<f32>, f32) {
+    let hop = ((sample_rate as f32 / ENVELOPE_RATE).round() as usize).max(1);
+    let mut last: Option<f32> = None;
+    let envelope = frames
+        .chunks(hop)
+        .map(|chunk| {
+            let energy =
+                chunk.iter().map(|it| it.avg() * it.avg()).sum::<f32>() / chunk.len() as f32;
+            // Compressed so that quiet hits count too, flooring out at about -40 dB
+            let level = (1. + 1e4 * energy).ln();
+            let rise = last.map_or(0., |last| (level - last).max(0.));
+            last = Some(level);
+            rise
+        })
+        .collect();
+    (envelope, sample_rate as f32 / hop as f32)
+}
+
+/// In-place radix-2 FFT of `re` and `im`, whose length is a power of two.
+/// `twiddles` holds `exp(-i * TAU * k / len)` for the first half of `k`.
+fn fft(re: &mut [f32], im: &mut [f32], twiddles: &[(f32, f32)]) {
+    let n = re.len();
+    let bits = n.trailing_zeros();
+    for i in 0..n {
+        let j = i.reverse_bits() >> (usize::BITS - bits);
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let half = len / 2;
+        let stride = n / len;
+        for start in (0..n).step_by(len) {
+            for k in 0..half {
+                let (cos, sin) = twiddles[k * stride];
+                let (a, b) = (start + k, start + k + half);
+                let (tr, ti) = (re[b] * cos - im[b] * sin, re[b] * sin + im[b] * cos);
+                re[b] = re[a] - tr;
+                im[b] = im[a] - ti;
+                re[a] += tr;
+                im[a] += ti;
+            }
+        }
+        len *= 2;
+    }
+}
+
+/// Half-wave rectified spectral flux over hops of about `1 / ENVELOPE_RATE`
+/// seconds: how much the level of each frequency bin rises from one hop to
+/// the next, summed over the bins. Unlike the energy, this also rises where a
+/// note changes under a steady level. Also returns the exact rate.
+fn spectral_flux(frames: &[Frame], sample_rate: u32) -> (Vec<f32>, f32) {
+    let hop = ((sample_rate as f32 / ENVELOPE_RATE).round() as usize).max(1);
+    // About 20 ms, long enough to tell notes apart
+    let size = ((sample_rate / 50) as usize).next_power_of_two().max(16);
+    let window: Vec<f32> = (0..size)
+        .map(|i| 0.5 - 0.5 * (TAU * i as f32 / size as f32).cos())
+        .collect();
+    let twiddles: Vec<(f32, f32)> = (0..size / 2)
+        .map(|k| {
+            let (sin, cos) = (-TAU * k as f32 / size as f32).sin_cos();
+            (cos, sin)
+        })
+        .collect();
+    let (mut re, mut im) = (vec![0.; size], vec![0.; size]);
+    let mut last: Option<Vec<f32>> = None;
+    // Hops whose window runs past the end are left out, as the cut would
+    // smear over the whole spectrum like a hit
+    let hops = (frames.len() + hop / 2).saturating_sub(size / 2) / hop;
+    let flux = (0..hops)
+        .map(|t| {
+            // Centered on the hop, so that peaks line up with the onsets
+            let start = (t * hop + hop / 2) as isize - (size / 2) as isize;
+            for (i, (re, weight)) in re.iter_mut().zip(&window).enumerate() {
+                let frame = usize::try_from(start + i as isize)
+                    .ok()
+                    .and_then(|it| frames.get(it));
+                *re = frame.map_or(0., |it| it.avg() * weight);
+            }
+            im.fill(0.);
+            fft(&mut re, &mut im, &twiddles);
+            // A sine of amplitude one peaks at about `size / 4`, so as with the
+            // energy, this floors out at about -40 dB
+            let levels = re[..=size / 2]
+                .iter()
+                .zip(&im)
+                .map(|(re, im)| (1. + 400. * re.hypot(*im) / size as f32).ln());
+            match &mut last {
+                Some(last) => levels
+                    .zip(last.iter_mut())
+                    .map(|(level, last)| (level - std::mem::replace(last, level)).max(0.))
+                    .sum(),
+                None => {
+                    last = Some(levels.collect());
+                    0.
+                }
+            }
+        })
+        .collect();
+    (flux, sample_rate as f32 / hop as f32)
+}
+
+pub(crate) fn estimate_bpm(frames: &[Frame], sample_rate: u32) -> Option<BpmEstimate> {
+    const MIN_BPM: f32 = 60.;
+    const MAX_BPM: f32 = 200.;
+    let (raw, rate) = onset_envelope(frames, sample_rate);
+    // Onsets rarely fall on the same spot of a hop, so spread each one over a
+    // few hops to let them line up across beats
+    const KERNEL: [f32; 5] = [1. / 9., 2. / 9., 3. / 9., 2. / 9., 1. / 9.];
+    let mut envelope: Vec<f32> = (0..raw.len())
+        .map(|i| {
+            KERNEL
+                .iter()
+                .enumerate()
+                .filter_map(|(k, weight)| Some(raw.get((i + k).checked_sub(2)?)? * weight))
+                .sum()
+        })
+        .collect();
+    let min_lag = (60. * rate / MAX_BPM).floor() as usize;
+    let max_lag = (60. * rate / MIN_BPM).ceil() as usize;
+    // A handful of beats at the slowest tempo at least
+    if envelope.len() < max_lag * 4 {
+        return None;
+    }
+    let mean = envelope.iter().sum::<f32>() / envelope.len() as f32;
+    for value in &mut envelope {
+        *value -= mean;
+    }
+    let energy: f32 = envelope.iter().map(|it| it * it).sum();
+    if energy <= 0. {
+        return None;
+    }
+
+    let correlate = |lag: usize| {
+        envelope[lag..]
+            .iter()
+            .zip(&envelope)
+            .map(|(a, b)| a * b)
+            .sum::<f32>()
+            / energy
+    };
+    // Half and double tempos correlate as well, so lean towards the tempos
+    // music usually has, with a width of an octave around 120 BPM
+    let weight = |lag: usize| {
+        let octaves = (60. * rate / lag as f32 / 120.).log2();
+        (-0.5 * octaves * octaves).exp()
+    };
+    let best = (min_lag..=max_lag)
+        .map(|lag| (lag, correlate(lag)))
+        .max_by(|a, b| (a.1 * weight(a.0)).total_cmp(&(b.1 * weight(b.0))))?;
+    let confidence = best.1.clamp(0., 1.);
+    if confidence < 0.1 {
+        return None;
+    }
+
+    // A hop is a coarse unit, so locate the peak a few beats out, where the
+    // same error is spread over more beats
+    let beats = (1..=4)
+        .rev()
+        .find(|beats| (best.0 + 1) * beats < envelope.len() / 2)?;
+    let (lag, peak) = (best.0 * beats - beats..=best.0 * beats + beats)
+        .map(|lag| (lag, correlate(lag)))
+        .max_by(|a, b| a.1.total_cmp(&b.1))?;
+    // The peak of a parabola through the neighbours gives a fractional lag
+    let (prev, next) = (correlate(lag - 1), correlate(lag + 1));
+    let curvature = prev - 2. * peak + next;
+    let offset = if curvature < 0. {
+        (0.5 * (prev - next) / curvature).clamp(-0.5, 0.5)
+    } else {
+        0.
+    };
+    let lag = (lag as f32 + offset) / beats as f32;
+    Some(BpmEstimate {
+        bpm: 60. * rate / lag,
+        confidence,
+    })
+}
+
+/// Picks the peaks of the spectral flux that stand out from their
+/// surroundings. `sensitivity` goes from 0 (only the strongest hits) to 1.
+pub(crate) fn detect_onsets(frames: &[Frame], sample_rate: u32, sensitivity: f32) -> Vec<f32> {
+    // Peaks must be the largest within this many seconds on either side
+    const SPACING: f32 = 0.03;
+    // Onsets are compared against the mean over this many seconds on either side
+    const CONTEXT: f32 = 0.1;
+    let (envelope, rate) = spectral_flux(frames, sample_rate);
+    let Some(max) = envelope.iter().copied().max_by(f32::total_cmp) else {
+        return Vec::new();
+    };
+    if max <= 0. {
+        return Vec::new();
+    }
+    let sensitivity = sensitivity.clamp(0., 1.);
+    let spacing = (SPACING * rate).round() as usize;
+    let context = (CONTEXT * rate).round() as usize;
+    // Even at full sensitivity, the faintest rises are just noise
+    let floor = (0.02 + 0.28 * (1. - sensitivity)) * max;
+    // Prefix sums for the local means
+    let mut sums = Vec::with_capacity(envelope.len() + 1);
+    sums.push(0.);
+    for value in &envelope {
+        sums.push(sums.last().unwrap() + value);
+    }
+    let window =
+        |i: usize, radius: usize| i.saturating_sub(radius)..(i + radius + 1).min(envelope.len());
+    envelope
+        .iter()
+        .enumerate()
+        .filter(|&(i, &value)| {
+            let local = window(i, context);
+            let mean = (sums[local.end] - sums[local.start]) / local.len() as f32;
+            value > floor
+                && value > mean * 1.5
+                // Ties go to the first of equal values
+                && envelope[i.saturating_sub(spacing)..i].iter().all(|&it| it < value)
+                && envelope[i..window(i, spacing).end].iter().all(|&it| it <= value)
+        })
+        .map(|(i, _)| i as f32 / rate)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    /// A sine at `low` Hz switching to `high` Hz at one second, its level
+    /// unchanged.
+    fn pitch_change(low: f32, high: f32) -> Vec<Frame> {
+        let mut phase = 0f32;
+        (0..SAMPLE_RATE * 2)
+            .map(|i| {
+                let frequency = if i < SAMPLE_RATE { low } else { high };
+                phase = (phase + TAU * frequency / SAMPLE_RATE as f32) % TAU;
+                Frame(0.5 * phase.sin(), 0.5 * phase.sin())
+            })
+            .collect()
+    }
+
+    /// Short clicks of a 1 kHz sine at `bpm`, for `seconds` seconds.
+    fn click_track(bpm: f32, seconds: f32) -> Vec<Frame> {
+        let period = 60. / bpm;
+        (0..(SAMPLE_RATE as f32 * seconds) as u32)
+            .map(|i| {
+                let time = i as f32 / SAMPLE_RATE as f32;
+                let since = time % period;
+                let sample = 0.5 * (-since * 200.).exp() * (TAU * 1000. * since).sin();
+                Frame(sample, sample)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn click_tracks_give_their_tempo() {
+        for bpm in [120., 90.] {
+            let estimate = estimate_bpm(&click_track(bpm, 20.), SAMPLE_RATE).unwrap();
+            assert!((estimate.bpm - bpm).abs() < 0.1, "{estimate:?}");
+            assert!(estimate.confidence > 0.5, "{estimate:?}");
+        }
+    }
+
+    #[test]
+    fn no_tempo_without_a_beat() {
+        let mut rng = crate::renderer::Rng::new(1);
+        let noise: Vec<_> = (0..SAMPLE_RATE * 20)
+            .map(|_| {
+                let sample = 0.5 * rng.next_f32();
+                Frame(sample, sample)
+            })
+            .collect();
+        assert!(estimate_bpm(&noise, SAMPLE_RATE).is_none());
+        // Too short for a handful of beats
+        assert!(estimate_bpm(&click_track(120., 1.), SAMPLE_RATE).is_none());
+    }
+
+    #[test]
+    fn fft_of_a_sine_peaks_at_its_bin() {
+        const SIZE: usize = 64;
+        let twiddles: Vec<_> = (0..SIZE / 2)
+            .map(|k| {
+                let (sin, cos) = (-TAU * k as f32 / SIZE as f32).sin_cos();
+                (cos, sin)
+            })
+            .collect();
+        let mut re: Vec<_> = (0..SIZE)
+            .map(|i| (TAU * 5. * i as f32 / SIZE as f32).cos())
+            .collect();
+        let mut im = vec![0.; SIZE];
+        fft(&mut re, &mut im, &twiddles);
+        for k in 0..SIZE {
+            let magnitude = re[k].hypot(im[k]);
+            let expected = if k == 5 || k == SIZE - 5 {
+                SIZE as f32 / 2.
+            } else {
+                0.
+            };
+            assert!((magnitude - expected).abs() < 1e-3, "bin {k}: {magnitude}");
+        }
+    }
+
+    #[test]
+    fn onsets_follow_hits() {
+        let mut rng = crate::renderer::Rng::new(1);
+        let hits = [0.25, 0.75, 1.25];
+        let frames: Vec<_> = (0..SAMPLE_RATE * 3 / 2)
+            .map(|i| {
+                let time = i as f32 / SAMPLE_RATE as f32;
+                let level = hits
+                    .iter()
+                    .filter(|&&hit| time >= hit)
+                    .map(|hit| (-(time - hit) * 50.).exp())
+                    .sum::<f32>();
+                let sample = 0.5 * level * rng.next_f32();
+                Frame(sample, sample)
+            })
+            .collect();
+        let onsets = detect_onsets(&frames, SAMPLE_RATE, 0.5);
+        assert_eq!(onsets.len(), hits.len(), "{onsets:?}");
+        for (onset, hit) in onsets.iter().zip(hits) {
+            assert!((onset - hit).abs() < 0.01, "{onsets:?}");
+        }
+    }
+
+    #[test]
+    fn onsets_include_pitch_changes_at_a_steady_level() {
+        let onsets = detect_onsets(&pitch_change(440., 660.), SAMPLE_RATE, 0.5);
+        // The start of the clip counts as well
+        assert!(
+            onsets.iter().all(|&it| it < 0.05 || (it - 1.).abs() < 0.02),
+            "{onsets:?}"
+        );
+        assert!(
+            onsets.iter().any(|&it| (it - 1.).abs() < 0.02),
+            "{onsets:?}"
+        );
+    }
+}
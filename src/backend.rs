@@ -4,22 +4,115 @@ pub mod cpal;
 #[cfg(feature = "oboe")]
 pub mod oboe;
 
-use crate::{
-    mixer::{Mixer, MixerCommand},
-    LatencyRecorder,
-};
+#[cfg(feature = "jack")]
+pub mod jack;
+
+use crate::{mixer::Mixer, LatencyRecorder};
 use anyhow::Result;
-use ringbuf::HeapConsumer;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
 
 pub struct BackendSetup {
-    pub(crate) mixer_cons: HeapConsumer<MixerCommand>,
+    pub(crate) mixer: Mixer,
     pub(crate) latency_rec: LatencyRecorder,
 }
 
+impl BackendSetup {
+    /// Tells the mixer the output's sample rate, for driving it from outside
+    /// of the built-in backends.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.mixer.set_sample_rate(sample_rate);
+    }
+
+    /// Renders the next block of interleaved output with `channels` channels
+    /// into `data`, as the built-in backends do in their callbacks.
+    pub fn render(&mut self, channels: u16, data: &mut [f32]) {
+        self.mixer.render(channels, data);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+#[non_exhaustive]
+pub enum BackendState {
+    Running,
+    /// The system paused the stream and is expected to resume it; restarting
+    /// won't help.
+    Interrupted,
+    /// The device is gone and the stream has to be restarted.
+    Disconnected,
+}
+
 pub trait Backend {
     fn setup(&mut self, setup: BackendSetup) -> Result<()>;
     fn start(&mut self) -> Result<()>;
+    /// Stops the stream, if any, until the next [`Backend::start`]. Does
+    /// nothing by default.
+    fn stop(&mut self) -> Result<()> {
+        Ok(())
+    }
     fn consume_broken(&self) -> bool;
+    /// Backends that can't tell the device is gone report it as running, and
+    /// rely on [`Backend::consume_broken`] alone.
+    fn state(&self) -> BackendState {
+        BackendState::Running
+    }
+    /// Whether a stream was started and is still running. Backends that don't
+    /// track it report `false`, which also skips the fade-out before stopping.
+    fn is_running(&self) -> bool {
+        false
+    }
+}
+
+/// Broken flag and [`BackendState`] shared between a backend and its callbacks.
+pub(crate) struct BackendStatus {
+    broken: AtomicBool,
+    state: AtomicU8,
+}
+
+impl Default for BackendStatus {
+    fn default() -> Self {
+        Self {
+            broken: AtomicBool::new(false),
+            state: AtomicU8::new(BackendState::Running as u8),
+        }
+    }
+}
+
+impl BackendStatus {
+    /// Marks the stream as broken, entering `state`.
+    pub fn set_broken(&self, state: BackendState) {
+        self.state.store(state as u8, Ordering::Relaxed);
+        self.broken.store(true, Ordering::Relaxed);
+    }
+
+    pub fn set_running(&self) {
+        self.state
+            .store(BackendState::Running as u8, Ordering::Relaxed);
+    }
+
+    /// Leaves [`BackendState::Interrupted`] once the system resumes the
+    /// stream, cheap enough to call from the audio callback.
+    #[cfg(feature = "oboe")]
+    pub fn resume(&self) {
+        let _ = self.state.compare_exchange(
+            BackendState::Interrupted as u8,
+            BackendState::Running as u8,
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        );
+    }
+
+    pub fn consume_broken(&self) -> bool {
+        self.broken.fetch_and(false, Ordering::Relaxed)
+    }
+
+    pub fn state(&self) -> BackendState {
+        match self.state.load(Ordering::Relaxed) {
+            0 => BackendState::Running,
+            1 => BackendState::Interrupted,
+            _ => BackendState::Disconnected,
+        }
+    }
 }
 
 #[repr(transparent)]
@@ -39,7 +132,7 @@ impl StateCell {
 impl From<BackendSetup> for StateCell {
     fn from(value: BackendSetup) -> Self {
         Self {
-            _data: (Mixer::new(0, value.mixer_cons), value.latency_rec),
+            _data: (value.mixer, value.latency_rec),
         }
     }
 }
@@ -1,25 +1,25 @@
-use crate::Backend;
-use anyhow::{anyhow, Context, Result};
+use crate::{Backend, SasaError};
+use anyhow::{bail, Context, Result};
 use cpal::{
     traits::{DeviceTrait, HostTrait, StreamTrait},
-    BufferSize, OutputCallbackInfo, Stream, StreamError,
-};
-use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    Arc,
+    BufferSize, BuildStreamError, OutputCallbackInfo, Stream, StreamError,
 };
+use std::sync::Arc;
 
-use super::{BackendSetup, StateCell};
+use super::{BackendSetup, BackendState, BackendStatus, StateCell};
 
 #[derive(Debug, Clone, Default)]
 pub struct CpalSettings {
     pub buffer_size: Option<u32>,
+    /// Output channel count, instead of the device's default. Starting fails
+    /// if the device doesn't support it at its default sample rate.
+    pub channels: Option<u16>,
 }
 
 pub struct CpalBackend {
     settings: CpalSettings,
     stream: Option<Stream>,
-    broken: Arc<AtomicBool>,
+    status: Arc<BackendStatus>,
     state: Option<Arc<StateCell>>,
 }
 
@@ -28,7 +28,7 @@ impl CpalBackend {
         Self {
             settings,
             stream: None,
-            broken: Arc::default(),
+            status: Arc::default(),
             state: None,
         }
     }
@@ -42,9 +42,9 @@ impl Backend for CpalBackend {
 
     fn start(&mut self) -> Result<()> {
         let host = cpal::default_host();
-        let device = host
-            .default_output_device()
-            .ok_or_else(|| anyhow!("no default output device is found"))?;
+        let device = host.default_output_device().ok_or_else(|| {
+            SasaError::DeviceUnavailable("no default output device is found".to_owned())
+        })?;
         let mut config = device
             .default_output_config()
             .context("cannot get output config")?
@@ -53,35 +53,40 @@ impl Backend for CpalBackend {
             .settings
             .buffer_size
             .map_or(BufferSize::Default, |it| BufferSize::Fixed(it));
+        if let Some(channels) = self.settings.channels {
+            let sample_rate = config.sample_rate;
+            let supported = device
+                .supported_output_configs()
+                .context("cannot get supported output configs")?
+                .any(|it| {
+                    it.channels() == channels
+                        && (it.min_sample_rate()..=it.max_sample_rate()).contains(&sample_rate)
+                });
+            if !supported {
+                bail!(SasaError::UnsupportedConfig(format!(
+                    "output device doesn't support {channels} channels at {} Hz",
+                    sample_rate.0
+                )));
+            }
+            config.channels = channels;
+        }
 
-        let broken = Arc::clone(&self.broken);
+        let status = Arc::clone(&self.status);
         let error_callback = move |err| {
             eprintln!("audio error: {err:?}");
             if matches!(err, StreamError::DeviceNotAvailable) {
-                broken.store(true, Ordering::Relaxed);
+                status.set_broken(BackendState::Disconnected);
             }
         };
         let state = Arc::clone(self.state.as_ref().unwrap());
-        state.get().0.sample_rate = config.sample_rate.0;
-        let stream = (if config.channels == 1 {
-            device.build_output_stream(
-                &config,
-                move |data: &mut [f32], info: &OutputCallbackInfo| {
-                    let (mixer, rec) = state.get();
-                    mixer.render_mono(data);
-                    let ts = info.timestamp();
-                    if let Some(delay) = ts.playback.duration_since(&ts.callback) {
-                        rec.push(delay.as_secs_f32());
-                    }
-                },
-                error_callback,
-            )
-        } else {
-            device.build_output_stream(
+        state.get().0.set_sample_rate(config.sample_rate.0);
+        let channels = config.channels;
+        let stream = device
+            .build_output_stream(
                 &config,
                 move |data: &mut [f32], info: &OutputCallbackInfo| {
                     let (mixer, rec) = state.get();
-                    mixer.render_stereo(data);
+                    mixer.render(channels, data);
                     let ts = info.timestamp();
                     if let Some(delay) = ts.playback.duration_since(&ts.callback) {
                         rec.push(delay.as_secs_f32());
@@ -89,14 +94,36 @@ impl Backend for CpalBackend {
                 },
                 error_callback,
             )
-        })
-        .context("failed to build stream")?;
+            .map_err(|err| match err {
+                BuildStreamError::DeviceNotAvailable => {
+                    SasaError::DeviceUnavailable(err.to_string()).into()
+                }
+                BuildStreamError::StreamConfigNotSupported => {
+                    SasaError::UnsupportedConfig(err.to_string()).into()
+                }
+                err => anyhow::Error::new(err),
+            })
+            .context("failed to build stream")?;
         stream.play()?;
         self.stream = Some(stream);
+        self.status.set_running();
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        self.stream = None;
         Ok(())
     }
 
     fn consume_broken(&self) -> bool {
-        self.broken.fetch_and(false, Ordering::Relaxed)
+        self.status.consume_broken()
+    }
+
+    fn state(&self) -> BackendState {
+        self.status.state()
+    }
+
+    fn is_running(&self) -> bool {
+        self.stream.is_some() && self.status.state() == BackendState::Running
     }
 }
@@ -0,0 +1,175 @@
+use super::{BackendSetup, BackendState, BackendStatus, StateCell};
+use crate::{Backend, SasaError};
+use anyhow::{Context, Result};
+use jack::{
+    AsyncClient, AudioOut, Client, ClientOptions, ClientStatus, Control, Frames, LatencyType,
+    NotificationHandler, Port, PortFlags, ProcessHandler, ProcessScope,
+};
+use std::sync::Arc;
+
+#[derive(Debug, Clone)]
+pub struct JackSettings {
+    pub client_name: String,
+    /// Connect the output ports to the physical playback ports once started.
+    pub auto_connect: bool,
+}
+impl Default for JackSettings {
+    fn default() -> Self {
+        Self {
+            client_name: "sasa".to_owned(),
+            auto_connect: true,
+        }
+    }
+}
+
+pub struct JackBackend {
+    settings: JackSettings,
+    client: Option<AsyncClient<JackNotification, JackProcess>>,
+    status: Arc<BackendStatus>,
+    state: Option<Arc<StateCell>>,
+}
+
+impl JackBackend {
+    pub fn new(settings: JackSettings) -> Self {
+        Self {
+            settings,
+            client: None,
+            status: Arc::default(),
+            state: None,
+        }
+    }
+}
+
+impl Backend for JackBackend {
+    fn setup(&mut self, setup: BackendSetup) -> Result<()> {
+        self.state = Some(Arc::new(setup.into()));
+        Ok(())
+    }
+
+    fn start(&mut self) -> Result<()> {
+        // Deactivate the previous client first so that the name is available again
+        self.client = None;
+
+        let (client, _) = Client::new(&self.settings.client_name, ClientOptions::NO_START_SERVER)
+            .map_err(|err| SasaError::DeviceUnavailable(err.to_string()))
+            .context("failed to connect to jack server")?;
+        let left = client
+            .register_port("out_left", AudioOut)
+            .context("failed to register port")?;
+        let right = client
+            .register_port("out_right", AudioOut)
+            .context("failed to register port")?;
+        let port_names = [left.name()?, right.name()?];
+
+        let state = Arc::clone(self.state.as_ref().unwrap());
+        state.get().0.set_sample_rate(client.sample_rate() as u32);
+        let buffer = vec![0.; client.buffer_size() as usize * 2];
+        let client = client
+            .activate_async(
+                JackNotification {
+                    status: Arc::clone(&self.status),
+                },
+                JackProcess {
+                    state,
+                    left,
+                    right,
+                    buffer,
+                },
+            )
+            .context("failed to activate jack client")?;
+
+        if self.settings.auto_connect {
+            let playback = client.as_client().ports(
+                None,
+                Some("audio"),
+                PortFlags::IS_INPUT | PortFlags::IS_PHYSICAL,
+            );
+            for (source, dest) in port_names.iter().zip(playback.iter()) {
+                client
+                    .as_client()
+                    .connect_ports_by_name(source, dest)
+                    .context("failed to connect ports")?;
+            }
+        }
+        self.client = Some(client);
+        self.status.set_running();
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        self.client = None;
+        Ok(())
+    }
+
+    fn consume_broken(&self) -> bool {
+        let broken = self.status.consume_broken();
+        // Reported here, the shutdown callback can't safely print
+        if broken && self.status.state() == BackendState::Disconnected {
+            eprintln!("audio error: jack shut down");
+        }
+        broken
+    }
+
+    fn state(&self) -> BackendState {
+        self.status.state()
+    }
+
+    fn is_running(&self) -> bool {
+        self.client.is_some() && self.status.state() == BackendState::Running
+    }
+}
+
+struct JackNotification {
+    status: Arc<BackendStatus>,
+}
+
+impl NotificationHandler for JackNotification {
+    // Runs like a signal handler, so this only flips the atomic status
+    unsafe fn shutdown(&mut self, _status: ClientStatus, _reason: &str) {
+        self.status.set_broken(BackendState::Disconnected);
+    }
+
+    fn xrun(&mut self, _: &Client) -> Control {
+        // Still reported as broken, but the client keeps running so recovery
+        // doesn't restart it
+        self.status.set_broken(BackendState::Running);
+        Control::Continue
+    }
+}
+
+struct JackProcess {
+    state: Arc<StateCell>,
+    left: Port<AudioOut>,
+    right: Port<AudioOut>,
+    buffer: Vec<f32>,
+}
+
+impl ProcessHandler for JackProcess {
+    fn process(&mut self, client: &Client, ps: &ProcessScope) -> Control {
+        let (mixer, rec) = self.state.get();
+        mixer.set_sample_rate(client.sample_rate() as u32);
+        // Sized in start and grown in buffer_size, never allocated here
+        let len = (ps.n_frames() as usize * 2).min(self.buffer.len());
+        let buffer = &mut self.buffer[..len];
+        mixer.render(2, buffer);
+
+        let (_, latency) = self.left.get_latency_range(LatencyType::Playback);
+        rec.push(latency as f32 / client.sample_rate() as f32);
+
+        let left = self.left.as_mut_slice(ps);
+        let right = self.right.as_mut_slice(ps);
+        for ((frame, left), right) in buffer.chunks_exact(2).zip(left).zip(right) {
+            *left = frame[0];
+            *right = frame[1];
+        }
+        Control::Continue
+    }
+
+    fn buffer_size(&mut self, _: &Client, size: Frames) -> Control {
+        let len = size as usize * 2;
+        if self.buffer.len() < len {
+            self.buffer.resize(len, 0.);
+        }
+        Control::Continue
+    }
+}
@@ -1,14 +1,16 @@
 pub use oboe::{PerformanceMode, Usage};
 
-use super::{BackendSetup, StateCell};
-use crate::Backend;
+use super::{BackendSetup, BackendState, BackendStatus, StateCell};
+use crate::{Backend, Frame};
 use anyhow::Result;
 use oboe::{
-    AudioOutputCallback, AudioOutputStreamSafe, AudioStream, AudioStreamAsync, AudioStreamBuilder,
-    DataCallbackResult, Output, SharingMode, Stereo,
+    AudioInputCallback, AudioInputStreamSafe, AudioOutputCallback, AudioOutputStreamSafe,
+    AudioStream, AudioStreamAsync, AudioStreamBase, AudioStreamBuilder, DataCallbackResult, Input,
+    Output, SharingMode, Stereo,
 };
+use ringbuf::{HeapConsumer, HeapProducer, HeapRb};
 use std::sync::{
-    atomic::{AtomicBool, Ordering},
+    atomic::{AtomicU32, Ordering},
     Arc,
 };
 
@@ -31,7 +33,7 @@ pub struct OboeBackend {
     settings: OboeSettings,
     stream: Option<AudioStreamAsync<Output, OboeCallback>>,
     state: Option<Arc<StateCell>>,
-    broken: Arc<AtomicBool>,
+    status: Arc<BackendStatus>,
 }
 
 impl OboeBackend {
@@ -40,7 +42,7 @@ impl OboeBackend {
             settings,
             stream: None,
             state: None,
-            broken: Arc::default(),
+            status: Arc::default(),
         }
     }
 }
@@ -60,32 +62,50 @@ impl Backend for OboeBackend {
             .set_channel_count::<Stereo>()
             .set_callback(OboeCallback::new(
                 Arc::clone(self.state.as_ref().unwrap()),
-                Arc::clone(&self.broken),
+                Arc::clone(&self.status),
                 self.settings.buffer_size,
             ))
             .open_stream()
             .unwrap();
         stream.start()?;
         self.stream = Some(stream);
+        self.status.set_running();
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        self.stream = None;
         Ok(())
     }
 
     fn consume_broken(&self) -> bool {
-        self.broken.fetch_and(false, Ordering::Relaxed)
+        self.status.consume_broken()
+    }
+
+    fn state(&self) -> BackendState {
+        self.status.state()
+    }
+
+    fn is_running(&self) -> bool {
+        self.stream.is_some() && self.status.state() == BackendState::Running
     }
 }
 
 struct OboeCallback {
     state: Arc<StateCell>,
-    broken: Arc<AtomicBool>,
+    status: Arc<BackendStatus>,
     buffer_size: Option<u32>,
 }
 
 impl OboeCallback {
-    pub fn new(state: Arc<StateCell>, broken: Arc<AtomicBool>, buffer_size: Option<u32>) -> Self {
+    pub fn new(
+        state: Arc<StateCell>,
+        status: Arc<BackendStatus>,
+        buffer_size: Option<u32>,
+    ) -> Self {
         Self {
             state,
-            broken,
+            status,
             buffer_size,
         }
     }
@@ -105,13 +125,14 @@ impl AudioOutputCallback for OboeCallback {
             );
         }
 
+        self.status.resume();
         let (mixer, rec) = self.state.get();
         if let Ok(latency) = stream.calculate_latency_millis() {
             rec.push((latency / 1000.) as f32);
         }
-        mixer.sample_rate = stream.get_sample_rate() as u32;
+        mixer.set_sample_rate(stream.get_sample_rate() as u32);
         let raw = frames.as_mut_ptr();
-        mixer.render_stereo(unsafe {
+        mixer.render(2, unsafe {
             std::slice::from_raw_parts_mut(raw as *mut f32, frames.len() * 2)
         });
 
@@ -124,7 +145,7 @@ impl AudioOutputCallback for OboeCallback {
         error: oboe::Error,
     ) {
         eprintln!("audio error: {error:?}");
-        self.broken.store(true, Ordering::Relaxed);
+        self.status.set_broken(error_state(error));
     }
 
     fn on_error_after_close(
@@ -133,6 +154,131 @@ impl AudioOutputCallback for OboeCallback {
         error: oboe::Error,
     ) {
         eprintln!("audio error: {error:?}");
-        self.broken.store(true, Ordering::Relaxed);
+        self.status.set_broken(error_state(error));
+    }
+}
+
+pub struct OboeInputSettings {
+    pub performance_mode: PerformanceMode,
+    /// Capacity of the capture ring buffer, in frames. Frames captured while
+    /// it is full are dropped.
+    pub ring_size: usize,
+}
+impl Default for OboeInputSettings {
+    fn default() -> Self {
+        Self {
+            performance_mode: PerformanceMode::None,
+            ring_size: 48000,
+        }
+    }
+}
+
+/// A capture stream pushing recorded frames into a ring buffer.
+pub struct OboeInputBackend {
+    settings: OboeInputSettings,
+    stream: Option<AudioStreamAsync<Input, OboeInputCallback>>,
+    sample_rate: Arc<AtomicU32>,
+    status: Arc<BackendStatus>,
+}
+
+impl OboeInputBackend {
+    pub fn new(settings: OboeInputSettings) -> Self {
+        Self {
+            settings,
+            stream: None,
+            sample_rate: Arc::default(),
+            status: Arc::default(),
+        }
+    }
+
+    /// (Re)starts capturing, returning the consumer end of a fresh ring buffer.
+    pub fn start(&mut self) -> Result<HeapConsumer<Frame>> {
+        self.stream = None;
+        let (prod, cons) = HeapRb::new(self.settings.ring_size.max(1)).split();
+        let mut stream = AudioStreamBuilder::default()
+            .set_input()
+            .set_performance_mode(self.settings.performance_mode)
+            .set_sharing_mode(SharingMode::Exclusive)
+            .set_format::<f32>()
+            .set_channel_count::<Stereo>()
+            .set_callback(OboeInputCallback {
+                prod,
+                sample_rate: Arc::clone(&self.sample_rate),
+                status: Arc::clone(&self.status),
+            })
+            .open_stream()?;
+        self.sample_rate
+            .store(stream.get_sample_rate() as u32, Ordering::Relaxed);
+        stream.start()?;
+        self.stream = Some(stream);
+        self.status.set_running();
+        Ok(cons)
+    }
+
+    pub fn stop(&mut self) {
+        self.stream = None;
+    }
+
+    /// The sample rate negotiated with the device, or zero before the first start.
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate.load(Ordering::Relaxed)
+    }
+
+    pub fn consume_broken(&self) -> bool {
+        self.status.consume_broken()
+    }
+
+    pub fn state(&self) -> BackendState {
+        self.status.state()
+    }
+}
+
+struct OboeInputCallback {
+    prod: HeapProducer<Frame>,
+    sample_rate: Arc<AtomicU32>,
+    status: Arc<BackendStatus>,
+}
+
+impl AudioInputCallback for OboeInputCallback {
+    type FrameType = (f32, Stereo);
+
+    fn on_audio_ready(
+        &mut self,
+        stream: &mut dyn AudioInputStreamSafe,
+        frames: &[(f32, f32)],
+    ) -> DataCallbackResult {
+        self.status.resume();
+        self.sample_rate
+            .store(stream.get_sample_rate() as u32, Ordering::Relaxed);
+        self.prod
+            .push_iter(&mut frames.iter().map(|&(left, right)| Frame(left, right)));
+        DataCallbackResult::Continue
+    }
+
+    fn on_error_before_close(
+        &mut self,
+        _audio_stream: &mut dyn AudioInputStreamSafe,
+        error: oboe::Error,
+    ) {
+        eprintln!("audio error: {error:?}");
+        self.status.set_broken(error_state(error));
+    }
+
+    fn on_error_after_close(
+        &mut self,
+        _audio_stream: &mut dyn AudioInputStreamSafe,
+        error: oboe::Error,
+    ) {
+        eprintln!("audio error: {error:?}");
+        self.status.set_broken(error_state(error));
+    }
+}
+
+/// Only a lost device needs a restart, other errors are the system
+/// interrupting the stream.
+fn error_state(error: oboe::Error) -> BackendState {
+    match error {
+        oboe::Error::Disconnected => BackendState::Disconnected,
+        _ => BackendState::Interrupted,
     }
 }
@@ -0,0 +1,180 @@
+use crate::{AudioClip, Frame};
+use anyhow::Result;
+use std::{borrow::Borrow, collections::HashMap, hash::Hash};
+
+struct Entry {
+    clip: AudioClip,
+    size: usize,
+    last_used: u64,
+}
+
+/// Decoded clips keyed by e.g. path or hash, evicting the least recently used
+/// ones once their frames exceed a byte budget.
+///
+/// Clips handed out share their frames with the cache, so an evicted clip
+/// stays valid for as long as someone still holds it.
+pub struct AudioClipCache<K> {
+    budget: usize,
+    used: usize,
+    tick: u64,
+    entries: HashMap<K, Entry>,
+}
+
+impl<K: Hash + Eq + Clone> AudioClipCache<K> {
+    pub fn new(budget: usize) -> Self {
+        Self {
+            budget,
+            used: 0,
+            tick: 0,
+            entries: HashMap::new(),
+        }
+    }
+
+    fn size_of(clip: &AudioClip) -> usize {
+        clip.frame_count() * std::mem::size_of::<Frame>()
+    }
+
+    pub fn get<Q>(&mut self, key: &Q) -> Option<AudioClip>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.tick += 1;
+        let entry = self.entries.get_mut(key)?;
+        entry.last_used = self.tick;
+        Some(entry.clip.clone())
+    }
+
+    /// Returns the cached clip for `key`, or loads it with `loader` and caches
+    /// the result. Errors from `loader` are passed through and nothing is cached.
+    pub fn get_or_load(
+        &mut self,
+        key: K,
+        loader: impl FnOnce() -> Result<AudioClip>,
+    ) -> Result<AudioClip> {
+        if let Some(clip) = self.get(&key) {
+            return Ok(clip);
+        }
+        let clip = loader()?;
+        self.insert(key, clip.clone());
+        Ok(clip)
+    }
+
+    /// Caches `clip` under `key`, then evicts least recently used clips until
+    /// the budget is met again. A clip larger than the whole budget is still
+    /// kept, until the next insertion.
+    pub fn insert(&mut self, key: K, clip: AudioClip) {
+        self.tick += 1;
+        let size = Self::size_of(&clip);
+        self.used += size;
+        if let Some(old) = self.entries.insert(
+            key.clone(),
+            Entry {
+                clip,
+                size,
+                last_used: self.tick,
+            },
+        ) {
+            self.used -= old.size;
+        }
+        while self.used > self.budget && self.entries.len() > 1 {
+            let oldest = self
+                .entries
+                .iter()
+                .filter(|(k, _)| **k != key)
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(k, _)| k.clone())
+                .unwrap();
+            self.remove(&oldest);
+        }
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<AudioClip>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let entry = self.entries.remove(key)?;
+        self.used -= entry.size;
+        Some(entry.clip)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.used = 0;
+    }
+
+    /// Bytes taken by the frames of cached clips.
+    pub fn used_bytes(&self) -> usize {
+        self.used
+    }
+
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: usize = std::mem::size_of::<Frame>();
+
+    fn clip(frames: usize) -> AudioClip {
+        AudioClip::from_raw(vec![Frame::default(); frames], 48000)
+    }
+
+    #[test]
+    fn least_recently_used_clips_are_evicted_first() {
+        let mut cache = AudioClipCache::new(30 * FRAME);
+        cache.insert("a", clip(10));
+        cache.insert("b", clip(10));
+        cache.insert("c", clip(10));
+        assert_eq!(cache.used_bytes(), 30 * FRAME);
+        // Makes "b" the oldest
+        assert!(cache.get("a").is_some());
+
+        cache.insert("d", clip(10));
+        assert!(cache.get("b").is_none());
+        assert_eq!(cache.len(), 3);
+        // Two need to go for this one, in order of last use
+        cache.insert("e", clip(20));
+        assert!(cache.get("c").is_none());
+        assert!(cache.get("a").is_none());
+        assert!(cache.get("d").is_some());
+        assert_eq!(cache.used_bytes(), 30 * FRAME);
+    }
+
+    #[test]
+    fn oversized_clip_is_kept_until_the_next_insert() {
+        let mut cache = AudioClipCache::new(10 * FRAME);
+        cache.insert("a", clip(5));
+        cache.insert("big", clip(50));
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.used_bytes(), 50 * FRAME);
+        cache.insert("b", clip(5));
+        assert!(cache.get("big").is_none());
+        assert_eq!(cache.used_bytes(), 5 * FRAME);
+    }
+
+    #[test]
+    fn failed_load_caches_nothing() {
+        let mut cache = AudioClipCache::<&str>::new(100 * FRAME);
+        assert!(cache
+            .get_or_load("a", || anyhow::bail!("no such file"))
+            .is_err());
+        assert!(cache.is_empty());
+        cache.get_or_load("a", || Ok(clip(10))).unwrap();
+        // Served from the cache this time
+        cache.get_or_load("a", || panic!("loaded twice")).unwrap();
+        assert_eq!(cache.used_bytes(), 10 * FRAME);
+    }
+}
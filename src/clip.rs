@@ -1,15 +1,84 @@
-use crate::Frame;
-use anyhow::{anyhow, bail, Result};
-use std::{io::Cursor, sync::Arc};
+use crate::{
+    analysis, resampler::kaiser_resample, wav, BpmEstimate, Frame, Resampler, SasaError, WavFormat,
+};
+use anyhow::{bail, Context, Result};
+use std::{
+    f32::consts::FRAC_1_SQRT_2,
+    fs::File,
+    io::{BufWriter, Cursor},
+    path::Path,
+    sync::Arc,
+};
 use symphonia::core::{
     audio::{AudioBufferRef, Signal},
-    io::MediaSourceStream,
+    formats::FormatReader,
+    io::{MediaSource, MediaSourceStream},
+    probe::Hint,
 };
 
+/// Left and right gains of each channel of the common layouts, in WAV order.
+/// These are the ITU-R BS.775 coefficients: the fronts keep unity, center and
+/// surrounds are added at -3 dB and the LFE is dropped.
+fn downmix_weights(channels: usize) -> Option<&'static [(f32, f32)]> {
+    const L: (f32, f32) = (1., 0.);
+    const R: (f32, f32) = (0., 1.);
+    const C: (f32, f32) = (FRAC_1_SQRT_2, FRAC_1_SQRT_2);
+    const LS: (f32, f32) = (FRAC_1_SQRT_2, 0.);
+    const RS: (f32, f32) = (0., FRAC_1_SQRT_2);
+    const LFE: (f32, f32) = (0., 0.);
+    Some(match channels {
+        3 => &[L, R, C],
+        // Surround (or back) channels follow the front ones
+        4 => &[L, R, LS, RS],
+        5 => &[L, R, C, LS, RS],
+        6 => &[L, R, C, LFE, LS, RS],
+        8 => &[L, R, C, LFE, LS, RS, LS, RS],
+        _ => return None,
+    })
+}
+
+/// Left and right gains folding `channels` down to stereo, by
+/// [`downmix_weights`] or averaged for other counts.
+fn downmix_gains(channels: usize) -> Vec<(f32, f32)> {
+    downmix_weights(channels).map_or_else(
+        || vec![(1. / channels as f32, 1. / channels as f32); channels],
+        <[_]>::to_vec,
+    )
+}
+
+fn decode_failed(err: symphonia::core::errors::Error) -> SasaError {
+    SasaError::DecodeFailed(err.to_string())
+}
+
+#[derive(Clone)]
 struct ClipInner {
     frames: Vec<Frame>,
     sample_rate: u32,
+    default_gain: f32,
+    original_channels: u16,
 }
+
+/// Output of [`AudioClip::decode_impl`].
+struct Decoded {
+    frames: Vec<Frame>,
+    sample_rate: u32,
+    channels: u16,
+    errors: usize,
+}
+/// A stream found by [`AudioClip::list_tracks`].
+#[derive(Debug, Clone)]
+pub struct TrackInfo {
+    /// Index to pass to [`AudioClip::decode_track`].
+    pub index: usize,
+    /// Short name of the codec, or `None` if it isn't supported.
+    pub codec: Option<&'static str>,
+    pub channels: Option<u16>,
+    pub sample_rate: Option<u32>,
+    /// Length in frames, if the container tells.
+    pub frames: Option<u64>,
+    pub language: Option<String>,
+}
+
 pub struct AudioClip(Arc<ClipInner>);
 impl Clone for AudioClip {
     fn clone(&self) -> Self {
@@ -19,13 +88,158 @@ impl Clone for AudioClip {
 
 impl AudioClip {
     pub fn from_raw(frames: Vec<Frame>, sample_rate: u32) -> Self {
+        Self::from_parts(frames, sample_rate, 2)
+    }
+
+    fn from_parts(frames: Vec<Frame>, sample_rate: u32, original_channels: u16) -> Self {
         Self(Arc::new(ClipInner {
             frames,
             sample_rate,
+            default_gain: 1.,
+            original_channels,
         }))
     }
 
+    /// Packs interleaved samples into a clip. Mono input is duplicated to both
+    /// channels. More channels are folded down by their usual WAV layout
+    /// (3.0, quad, 5.0, 5.1 and 7.1) as in ITU-R BS.775: the fronts go to
+    /// their own side unchanged, the center to both sides and the surrounds to
+    /// their own side at -3 dB, and the LFE is dropped. The result can exceed
+    /// full scale when every channel is loud. Other channel counts are
+    /// averaged into both sides.
+    pub fn from_interleaved(data: Vec<f32>, channels: u16, sample_rate: u32) -> Result<Self> {
+        let original_channels = channels;
+        let channels = channels as usize;
+        if channels == 0 {
+            bail!(SasaError::InvalidParameter(
+                "channel count must be positive".to_owned()
+            ));
+        }
+        if !data.chunks_exact(channels).remainder().is_empty() {
+            bail!(SasaError::InvalidParameter(format!(
+                "sample count {} is not divisible by channel count {channels}",
+                data.len()
+            )));
+        }
+        let frames = match channels {
+            1 => data.into_iter().map(|it| Frame(it, it)).collect(),
+            2 => data.chunks_exact(2).map(|it| Frame(it[0], it[1])).collect(),
+            _ => {
+                let weights = downmix_gains(channels);
+                data.chunks_exact(channels)
+                    .map(|it| {
+                        it.iter()
+                            .zip(&weights)
+                            .fold(Frame::default(), |frame, (&sample, &(wl, wr))| {
+                                frame + Frame(sample * wl, sample * wr)
+                            })
+                    })
+                    .collect()
+            }
+        };
+        Ok(Self::from_parts(frames, sample_rate, original_channels))
+    }
+
+    /// Sets the gain renderers apply to this clip on top of their own
+    /// amplifier, for leveling assets without baking it into the samples.
+    ///
+    /// If the clip is already shared with other handles, its frames will be
+    /// copied.
+    pub fn with_default_gain(mut self, gain: f32) -> Self {
+        Arc::make_mut(&mut self.0).default_gain = gain;
+        self
+    }
+
     pub fn decode(data: Vec<u8>) -> Result<(Vec<Frame>, u32)> {
+        let decoded = Self::decode_impl(data, None, false, &mut |_, _| {})?;
+        Ok((decoded.frames, decoded.sample_rate))
+    }
+
+    /// Lists the tracks of a container, e.g. to pick a language for
+    /// [`AudioClip::decode_track`].
+    pub fn list_tracks(data: Vec<u8>) -> Result<Vec<TrackInfo>> {
+        let codecs = symphonia::default::get_codecs();
+        Ok(Self::probe(Box::new(Cursor::new(data)), None)?
+            .tracks()
+            .iter()
+            .enumerate()
+            .map(|(index, track)| {
+                let params = &track.codec_params;
+                TrackInfo {
+                    index,
+                    codec: codecs.get_codec(params.codec).map(|it| it.short_name),
+                    channels: params.channels.map(|it| it.count() as u16),
+                    sample_rate: params.sample_rate,
+                    frames: params.n_frames,
+                    language: track.language.clone(),
+                }
+            })
+            .collect())
+    }
+
+    /// Like [`AudioClip::decode`], but decodes the track at `index` of
+    /// [`AudioClip::list_tracks`] instead of the default one.
+    pub fn decode_track(data: Vec<u8>, index: usize) -> Result<(Vec<Frame>, u32)> {
+        let decoded = Self::decode_impl(data, Some(index), false, &mut |_, _| {})?;
+        Ok((decoded.frames, decoded.sample_rate))
+    }
+
+    /// Like [`AudioClip::decode`], calling `progress` after every packet with
+    /// the number of frames decoded so far and, if the container tells, the
+    /// total.
+    pub fn decode_with_progress(
+        data: Vec<u8>,
+        mut progress: impl FnMut(usize, Option<u64>),
+    ) -> Result<(Vec<Frame>, u32)> {
+        let decoded = Self::decode_impl(data, None, false, &mut progress)?;
+        Ok((decoded.frames, decoded.sample_rate))
+    }
+
+    /// Like [`AudioClip::decode`], but skips packets that fail to decode and
+    /// stops at a read error instead of failing, keeping whatever was decoded.
+    /// Also returns the number of such errors.
+    ///
+    /// Still fails if the format can't be recognized at all.
+    pub fn decode_lossy(data: Vec<u8>) -> Result<(Vec<Frame>, u32, usize)> {
+        let decoded = Self::decode_impl(data, None, true, &mut |_, _| {})?;
+        Ok((decoded.frames, decoded.sample_rate, decoded.errors))
+    }
+
+    /// Opens `source`, with `extension` helping to guess the format.
+    fn probe(
+        source: Box<dyn MediaSource>,
+        extension: Option<&str>,
+    ) -> Result<Box<dyn FormatReader>> {
+        let mss = MediaSourceStream::new(source, Default::default());
+        let mut hint = Hint::new();
+        if let Some(extension) = extension {
+            hint.with_extension(extension);
+        }
+        Ok(symphonia::default::get_probe()
+            .format(&hint, mss, &Default::default(), &Default::default())
+            .map_err(decode_failed)?
+            .format)
+    }
+
+    /// Decodes the track at `track` of the container's list, or the default
+    /// one with `None`.
+    fn decode_impl(
+        data: Vec<u8>,
+        track: Option<usize>,
+        lossy: bool,
+        progress: &mut dyn FnMut(usize, Option<u64>),
+    ) -> Result<Decoded> {
+        Self::decode_source(Box::new(Cursor::new(data)), None, track, lossy, progress)
+    }
+
+    /// Like [`AudioClip::decode_impl`], reading from any source.
+    fn decode_source(
+        source: Box<dyn MediaSource>,
+        extension: Option<&str>,
+        track: Option<usize>,
+        lossy: bool,
+        progress: &mut dyn FnMut(usize, Option<u64>),
+    ) -> Result<Decoded> {
         fn load_frames_from_buffer(
             frames: &mut Vec<Frame>,
             buffer: &symphonia::core::audio::AudioBuffer<f32>,
@@ -36,11 +250,25 @@ impl AudioClip {
                     frames.reserve(chan.len());
                     frames.extend(chan.iter().map(|&it| Frame(it, it)));
                 }
-                _ => {
+                2 => {
                     let iter = buffer.chan(0).iter().zip(buffer.chan(1));
                     frames.reserve(iter.len());
                     frames.extend(iter.map(|(left, right)| Frame(*left, *right)))
                 }
+                // Folded down the same way as by `from_interleaved`
+                channels => {
+                    let weights = downmix_gains(channels);
+                    frames.reserve(buffer.frames());
+                    frames.extend((0..buffer.frames()).map(|i| {
+                        weights.iter().enumerate().fold(
+                            Frame::default(),
+                            |frame, (channel, &(wl, wr))| {
+                                let sample = buffer.chan(channel)[i];
+                                frame + Frame(sample * wl, sample * wr)
+                            },
+                        )
+                    }))
+                }
             }
         }
 
@@ -74,28 +302,37 @@ impl AudioClip {
             Ok(())
         }
         let codecs = symphonia::default::get_codecs();
-        let probe = symphonia::default::get_probe();
-        let mss = MediaSourceStream::new(Box::new(Cursor::new(data)), Default::default());
-        let mut format_reader = probe
-            .format(
-                &Default::default(),
-                mss,
-                &Default::default(),
-                &Default::default(),
-            )?
-            .format;
-        let codec_params = &format_reader
-            .default_track()
-            .ok_or_else(|| anyhow!("default track not found"))?
-            .codec_params;
+        let mut format_reader = Self::probe(source, extension)?;
+        let track = match track {
+            Some(index) => format_reader.tracks().get(index).ok_or_else(|| {
+                SasaError::DecodeFailed(format!(
+                    "track {index} not found, the container has {}",
+                    format_reader.tracks().len()
+                ))
+            })?,
+            None => format_reader
+                .default_track()
+                .ok_or_else(|| SasaError::DecodeFailed("default track not found".to_owned()))?,
+        };
+        let track_id = track.id;
+        let codec_params = &track.codec_params;
         let sample_rate = codec_params
             .sample_rate
-            .ok_or_else(|| anyhow!("unknown sample rate"))?;
-        let mut decoder = codecs.make(codec_params, &Default::default())?;
+            .ok_or_else(|| SasaError::DecodeFailed("unknown sample rate".to_owned()))?;
+        let total = codec_params.n_frames;
+        let mut channels = codec_params.channels.map(|it| it.count() as u16);
+        let mut decoder = codecs
+            .make(codec_params, &Default::default())
+            .map_err(decode_failed)?;
         let mut frames = Vec::new();
+        let mut errors = 0;
         loop {
             match format_reader.next_packet() {
                 Ok(packet) => {
+                    // Other tracks are interleaved in the same stream
+                    if packet.track_id() != track_id {
+                        continue;
+                    }
                     let buffer = match decoder.decode(&packet) {
                         Ok(buffer) => buffer,
                         Err(symphonia::core::errors::Error::DecodeError(s))
@@ -103,9 +340,27 @@ impl AudioClip {
                         {
                             continue;
                         }
-                        Err(err) => return Err(err.into()),
+                        Err(symphonia::core::errors::Error::DecodeError(_)) if lossy => {
+                            errors += 1;
+                            continue;
+                        }
+                        Err(err) => bail!(decode_failed(err)),
                     };
+                    if buffer.spec().rate != sample_rate {
+                        // Clips have a single rate, so a stream switching
+                        // mid-way can't be represented
+                        if lossy {
+                            errors += 1;
+                            continue;
+                        }
+                        bail!(SasaError::DecodeFailed(format!(
+                            "sample rate changed from {sample_rate} to {} mid-stream",
+                            buffer.spec().rate
+                        )));
+                    }
+                    channels.get_or_insert(buffer.spec().channels.count() as u16);
                     load_frames_from_buffer_ref(&mut frames, &buffer)?;
+                    progress(frames.len(), total);
                 }
                 Err(error) => match error {
                     symphonia::core::errors::Error::IoError(error)
@@ -113,28 +368,101 @@ impl AudioClip {
                     {
                         break;
                     }
-                    _ => bail!(error),
+                    _ if lossy => {
+                        errors += 1;
+                        break;
+                    }
+                    _ => bail!(decode_failed(error)),
                 },
             }
         }
-        Ok((frames, sample_rate))
+        Ok(Decoded {
+            frames,
+            sample_rate,
+            channels: channels.unwrap_or(2),
+            errors,
+        })
     }
 
     #[inline]
     pub fn new(data: Vec<u8>) -> Result<Self> {
-        let (frames, sample_rate) = Self::decode(data)?;
-        Ok(Self::from_raw(frames, sample_rate))
+        let decoded = Self::decode_impl(data, None, false, &mut |_, _| {})?;
+        Ok(Self::from_parts(
+            decoded.frames,
+            decoded.sample_rate,
+            decoded.channels,
+        ))
+    }
+
+    /// Decodes the file at `path`, reading it as decoding goes instead of
+    /// loading all of its compressed data into memory first. The extension
+    /// helps to recognize the format.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path).with_context(|| format!("open {}", path.display()))?;
+        let extension = path.extension().and_then(|it| it.to_str());
+        let decoded = Self::decode_source(Box::new(file), extension, None, false, &mut |_, _| {})?;
+        Ok(Self::from_parts(
+            decoded.frames,
+            decoded.sample_rate,
+            decoded.channels,
+        ))
+    }
+
+    /// Decodes like [`AudioClip::decode_lossy`], returning the clip along with
+    /// the number of errors skipped.
+    pub fn new_lossy(data: Vec<u8>) -> Result<(Self, usize)> {
+        let decoded = Self::decode_impl(data, None, true, &mut |_, _| {})?;
+        Ok((
+            Self::from_parts(decoded.frames, decoded.sample_rate, decoded.channels),
+            decoded.errors,
+        ))
     }
 
+    #[inline]
     pub fn sample(&self, position: f32) -> Option<Frame> {
-        let position = position * self.0.sample_rate as f32;
-        let actual_index = position as usize;
-        if let Some(frame) = self.0.frames.get(actual_index) {
-            let next_frame = self.0.frames.get(actual_index + 1).unwrap_or(frame);
-            Some(frame.interpolate(next_frame, position - actual_index as f32))
-        } else {
-            None
+        self.sample_with(position, Resampler::Linear)
+    }
+
+    /// Samples the clip at `position` seconds using the given interpolation.
+    pub fn sample_with(&self, position: f32, resampler: Resampler) -> Option<Frame> {
+        resampler.sample(&self.0.frames, position as f64 * self.0.sample_rate as f64)
+    }
+
+    /// Converts the clip to `sample_rate` ahead of time, so that it doesn't
+    /// have to be resampled while playing.
+    ///
+    /// With [`Resampler::Sinc`] this uses a slower Kaiser-windowed sinc that
+    /// also filters out what the new rate can't represent.
+    pub fn resampled(&self, sample_rate: u32, resampler: Resampler) -> Self {
+        if sample_rate == self.0.sample_rate {
+            return self.clone();
         }
+        let ratio = self.0.sample_rate as f64 / sample_rate as f64;
+        let frames = if let Resampler::Sinc(taps) = resampler {
+            kaiser_resample(&self.0.frames, ratio, taps)
+        } else {
+            let len = (self.0.frames.len() as f64 / ratio).ceil() as usize;
+            (0..len)
+                .map_while(|i| resampler.sample(&self.0.frames, i as f64 * ratio))
+                .collect()
+        };
+        Self::from_parts(frames, sample_rate, self.original_channels())
+            .with_default_gain(self.default_gain())
+    }
+
+    /// Writes the clip to a stereo WAV file. The default gain is not applied.
+    ///
+    /// Fails on clips past the 4 GiB the format can describe, around three
+    /// hours of [`WavFormat::Float32`] at 48 kHz.
+    pub fn write_wav(&self, path: impl AsRef<Path>, format: WavFormat) -> Result<()> {
+        let file = File::create(path).context("create wav file")?;
+        wav::write(
+            BufWriter::new(file),
+            self.frames(),
+            self.sample_rate(),
+            format,
+        )
     }
 
     #[inline(always)]
@@ -142,11 +470,37 @@ impl AudioClip {
         &self.0.frames
     }
 
+    /// The frames as `[left, right]` pairs, without copying.
+    #[inline(always)]
+    pub fn as_f32_pairs(&self) -> &[[f32; 2]] {
+        let frames = self.frames();
+        // SAFETY: `Frame` is `repr(C)` with two `f32`s, so it has the layout of `[f32; 2]`
+        unsafe { std::slice::from_raw_parts(frames.as_ptr() as *const [f32; 2], frames.len()) }
+    }
+
+    /// Copies the frames into interleaved stereo samples.
+    pub fn as_interleaved(&self) -> Vec<f32> {
+        self.as_f32_pairs().concat()
+    }
+
     #[inline(always)]
     pub fn sample_rate(&self) -> u32 {
         self.0.sample_rate
     }
 
+    #[inline(always)]
+    pub fn default_gain(&self) -> f32 {
+        self.0.default_gain
+    }
+
+    /// Number of channels the clip had before being converted to stereo: as
+    /// decoded, or as passed to [`AudioClip::from_interleaved`]. Clips made
+    /// with [`AudioClip::from_raw`] count as stereo.
+    #[inline(always)]
+    pub fn original_channels(&self) -> u16 {
+        self.0.original_channels
+    }
+
     #[inline(always)]
     pub fn frame_count(&self) -> usize {
         self.0.frames.len()
@@ -155,4 +509,164 @@ impl AudioClip {
     pub fn length(&self) -> f32 {
         self.frame_count() as f32 / self.sample_rate() as f32
     }
+
+    /// Largest absolute sample over both channels, ignoring
+    /// [`AudioClip::default_gain`]. 1 is 0 dBFS.
+    pub fn peak(&self) -> f32 {
+        self.as_f32_pairs()
+            .iter()
+            .flat_map(|pair| pair.iter())
+            .fold(0., |peak, sample| peak.max(sample.abs()))
+    }
+
+    /// Guesses the tempo from how regularly the energy rises, between 60 and
+    /// 200 BPM. This is a heuristic: it may well land on half, double or two
+    /// thirds of the tempo a musician would name, and returns `None` for clips
+    /// shorter than a few seconds or without a clear pulse.
+    pub fn estimate_bpm(&self) -> Option<BpmEstimate> {
+        analysis::estimate_bpm(self.frames(), self.sample_rate())
+    }
+
+    /// Times in seconds where notes or hits start, as candidates for chart
+    /// markers. Detection follows rises in the spectrum (spectral flux), so a
+    /// new note counts even when the level stays the same, and `sensitivity`
+    /// from 0 to 1 lets weaker ones through. Onsets closer than 30 ms merge
+    /// into one.
+    pub fn detect_onsets(&self, sensitivity: f32) -> Vec<f32> {
+        analysis::detect_onsets(self.frames(), self.sample_rate(), sensitivity)
+    }
+
+    /// Root mean square of all samples over both channels, ignoring
+    /// [`AudioClip::default_gain`]. Zero for an empty clip.
+    pub fn rms(&self) -> f32 {
+        let pairs = self.as_f32_pairs();
+        if pairs.is_empty() {
+            return 0.;
+        }
+        // Summed in f64 so that long clips don't lose the small samples
+        let sum: f64 = pairs
+            .iter()
+            .flat_map(|pair| pair.iter())
+            .map(|&it| it as f64 * it as f64)
+            .sum();
+        (sum / (pairs.len() * 2) as f64).sqrt() as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Downmixes one frame with a single channel set to one.
+    fn downmix(channels: usize, channel: usize) -> Frame {
+        let mut data = vec![0.; channels];
+        data[channel] = 1.;
+        AudioClip::from_interleaved(data, channels as u16, 48000)
+            .unwrap()
+            .frames()[0]
+    }
+
+    #[test]
+    fn five_one_is_downmixed_by_layout() {
+        // L R C LFE Ls Rs
+        let expected = [
+            (1., 0.),
+            (0., 1.),
+            (FRAC_1_SQRT_2, FRAC_1_SQRT_2),
+            (0., 0.),
+            (FRAC_1_SQRT_2, 0.),
+            (0., FRAC_1_SQRT_2),
+        ];
+        for (channel, (left, right)) in expected.into_iter().enumerate() {
+            let frame = downmix(6, channel);
+            assert!((frame.0 - left).abs() < 1e-6 && (frame.1 - right).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn five_one_front_left_keeps_unity_gain() {
+        let data: Vec<f32> = (0..600)
+            .map(|i| {
+                if i % 6 == 0 {
+                    (i as f32 * 0.01).sin()
+                } else {
+                    0.
+                }
+            })
+            .collect();
+        let clip = AudioClip::from_interleaved(data.clone(), 6, 48000).unwrap();
+        for (frame, &sample) in clip.frames().iter().zip(data.iter().step_by(6)) {
+            assert_eq!((frame.0, frame.1), (sample, 0.));
+        }
+    }
+
+    #[test]
+    fn peak_and_rms_ignore_default_gain() {
+        let clip = AudioClip::from_raw(vec![Frame(0.5, -0.5), Frame(0., -1.)], 48000)
+            .with_default_gain(2.);
+        assert_eq!(clip.peak(), 1.);
+        assert!((clip.rms() - 0.375f32.sqrt()).abs() < 1e-6);
+        let empty = AudioClip::from_raw(Vec::new(), 48000);
+        assert_eq!(empty.peak(), 0.);
+        assert_eq!(empty.rms(), 0.);
+    }
+
+    #[test]
+    fn from_file_decodes_like_from_memory() {
+        let data: Vec<f32> = (0..2000).map(|i| (i as f32 * 0.01).sin()).collect();
+        let path = std::env::temp_dir().join(format!("sasa-from-file-{}.wav", std::process::id()));
+        std::fs::write(&path, float_wav(&data, 2, 44100)).unwrap();
+        let file = AudioClip::from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        let file = file.unwrap();
+        assert_eq!(file.sample_rate(), 44100);
+        assert_eq!(file.as_interleaved(), data);
+        assert!(AudioClip::from_file(&path).is_err());
+    }
+
+    /// A 32-bit float WAV file holding interleaved `data`.
+    fn float_wav(data: &[f32], channels: u16, sample_rate: u32) -> Vec<u8> {
+        let block_align = channels * 4;
+        let data_len = data.len() as u32 * 4;
+        let mut wav = Vec::new();
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(4 + 8 + 16 + 8 + data_len).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&3u16.to_le_bytes());
+        wav.extend_from_slice(&channels.to_le_bytes());
+        wav.extend_from_slice(&sample_rate.to_le_bytes());
+        wav.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+        wav.extend_from_slice(&block_align.to_le_bytes());
+        wav.extend_from_slice(&32u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_len.to_le_bytes());
+        for sample in data {
+            wav.extend_from_slice(&sample.to_le_bytes());
+        }
+        wav
+    }
+
+    #[test]
+    fn decoded_surround_matches_interleaved_downmix() {
+        for channels in [6, 7] {
+            let data: Vec<f32> = (0..channels * 100)
+                .map(|i| ((i * 37 % 101) as f32 / 50. - 1.) * 0.9)
+                .collect();
+            let wav = float_wav(&data, channels as u16, 48000);
+            let decoded = AudioClip::new(wav).unwrap();
+            let interleaved = AudioClip::from_interleaved(data, channels as u16, 48000).unwrap();
+            assert_eq!(decoded.original_channels(), channels as u16);
+            assert_eq!(decoded.as_f32_pairs(), interleaved.as_f32_pairs());
+        }
+    }
+
+    #[test]
+    fn unknown_layout_is_averaged() {
+        for channel in 0..7 {
+            let frame = downmix(7, channel);
+            assert!((frame.0 - 1. / 7.).abs() < 1e-6);
+            assert_eq!(frame.0, frame.1);
+        }
+    }
 }
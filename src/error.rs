@@ -0,0 +1,69 @@
+use thiserror::Error;
+
+/// Failures worth telling apart, e.g. to retry when a buffer is full or to
+/// recover when the device went away.
+///
+/// Fallible functions still return [`anyhow::Result`], with one of these at the
+/// root of the error whenever the cause is known. Context added on top doesn't
+/// get in the way of `error.downcast_ref::<SasaError>()`.
+#[derive(Debug, Error)]
+pub enum SasaError {
+    /// A command or voice buffer is full until the audio thread drains it.
+    /// Retrying after the next callback, or enlarging the buffer, helps.
+    #[error("buffer is full")]
+    BufferFull,
+    /// The cap set with [`crate::AudioManagerBuilder::max_renderers`] is reached.
+    #[error("too many renderers (at most {0})")]
+    TooManyRenderers(usize),
+    /// No output device or audio server could be used.
+    #[error("{0}")]
+    DeviceUnavailable(String),
+    /// The device doesn't support the requested stream configuration.
+    #[error("{0}")]
+    UnsupportedConfig(String),
+    /// The data couldn't be decoded as audio.
+    #[error("{0}")]
+    DecodeFailed(String),
+    /// An argument is out of range or otherwise unusable.
+    #[error("{0}")]
+    InvalidParameter(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AudioClip, Frame, Music, MusicParams};
+    use anyhow::Context;
+
+    #[test]
+    fn root_cause_survives_context() {
+        let clip = AudioClip::from_raw(vec![Frame::default(); 100], 48000);
+        let params = MusicParams {
+            command_buffer_size: 1,
+            ..MusicParams::default()
+        };
+        let (mut music, _renderer) = Music::new_offline(clip, params);
+        music.play().unwrap();
+        let err = music.play().context("start level music").unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(SasaError::BufferFull)));
+
+        let err = MusicParams::builder()
+            .playback_rate(-1.)
+            .build()
+            .context("load level")
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(SasaError::InvalidParameter(_))
+        ));
+
+        let err = AudioClip::new(b"definitely not audio".to_vec())
+            .context("load hit sound")
+            .err()
+            .unwrap();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(SasaError::DecodeFailed(_))
+        ));
+    }
+}
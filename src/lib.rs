@@ -1,37 +1,83 @@
 /// Simple And Stupid Audio for Rust, optimized for low latency.
 pub mod backend;
-pub use backend::Backend;
+pub use backend::{Backend, BackendState};
+
+mod analysis;
+pub use analysis::BpmEstimate;
+
+mod cache;
+pub use cache::AudioClipCache;
 
 mod clip;
-pub use clip::AudioClip;
+pub use clip::{AudioClip, TrackInfo};
+
+mod error;
+pub use error::SasaError;
 
 mod mixer;
+pub use mixer::{MonoDownmix, RendererInfo};
+
+mod resampler;
+pub use resampler::Resampler;
 
 mod renderer;
-pub use renderer::{Music, MusicParams, PlaySfxParams, Renderer, Sfx};
+pub use renderer::{
+    mix_frame, render_frames, DelayParams, Impulse, LoopMode, Metronome, MetronomeParams, Music,
+    MusicParams, MusicParamsBuilder, MusicStatus, MusicUpdate, Noise, NoiseColor, NoiseParams,
+    Oscillator, PanLaw, PlaySfxParams, RadioParams, Renderer, Sfx, SfxPool, SfxPoolParams,
+    ToneParams, Waveform,
+};
+
+mod tempo;
+pub use tempo::TempoMap;
+
+mod wav;
+pub use wav::WavFormat;
 
-use crate::{backend::BackendSetup, mixer::MixerCommand};
-use anyhow::{anyhow, Context, Result};
+use crate::{
+    backend::BackendSetup,
+    mixer::{FadeRequest, Mixer, MixerCommand, MixerState},
+};
+use anyhow::{bail, Context, Result};
 use ringbuf::{HeapProducer, HeapRb};
 use std::{
-    ops::{Add, Mul},
+    ops::{Add, Mul, Sub},
     sync::{
         atomic::{AtomicU32, Ordering},
         Arc,
     },
+    time::{Duration, Instant},
 };
 
+/// Flushes values too small to hear to zero, so that decaying filter state
+/// doesn't end up in slow denormal arithmetic.
+#[inline(always)]
+fn flush_denormal(value: f32) -> f32 {
+    if value.abs() < 1e-15 {
+        0.
+    } else {
+        value
+    }
+}
+
 fn buffer_is_full<E>(_: E) -> anyhow::Error {
-    anyhow!("buffer is full")
+    SasaError::BufferFull.into()
 }
 
+/// A stereo frame. Laid out like `[f32; 2]`.
 #[derive(Clone, Copy, Default)]
+#[repr(C)]
 pub struct Frame(pub f32, pub f32);
 impl Frame {
     pub fn avg(&self) -> f32 {
         (self.0 + self.1) / 2.
     }
 
+    #[inline(always)]
+    fn flush_denormals(self) -> Self {
+        Self(flush_denormal(self.0), flush_denormal(self.1))
+    }
+
     pub fn interpolate(&self, other: &Self, f: f32) -> Self {
         Self(
             self.0 + (other.0 - self.0) * f,
@@ -46,6 +92,13 @@ impl Add for Frame {
         Self(self.0 + rhs.0, self.1 + rhs.1)
     }
 }
+impl Sub for Frame {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Self(self.0 - rhs.0, self.1 - rhs.1)
+    }
+}
 impl Mul<f32> for Frame {
     type Output = Self;
 
@@ -54,10 +107,10 @@ impl Mul<f32> for Frame {
     }
 }
 
-const LATENCY_RECORD_NUM: usize = 64;
+const DEFAULT_LATENCY_RECORD_NUM: usize = 64;
 
 pub struct LatencyRecorder {
-    records: [f32; LATENCY_RECORD_NUM],
+    records: Vec<f32>,
     head: usize,
     sum: f32,
     full: bool,
@@ -65,9 +118,10 @@ pub struct LatencyRecorder {
 }
 
 impl LatencyRecorder {
-    pub fn new(result: Arc<AtomicU32>) -> Self {
+    /// Creates a recorder averaging over the last `window` records.
+    pub fn new(window: usize, result: Arc<AtomicU32>) -> Self {
         Self {
-            records: [0.; LATENCY_RECORD_NUM],
+            records: vec![0.; window.max(1)],
             head: 0,
             sum: 0.,
             full: false,
@@ -80,14 +134,14 @@ impl LatencyRecorder {
         self.sum += record - *place;
         *place = record;
         self.head += 1;
-        if self.head == LATENCY_RECORD_NUM {
+        if self.head == self.records.len() {
             self.full = true;
             self.head = 0;
         }
         self.result.store(
             (self.sum
                 / (if self.full {
-                    LATENCY_RECORD_NUM
+                    self.records.len()
                 } else {
                     self.head.max(1)
                 }) as f32)
@@ -97,72 +151,838 @@ impl LatencyRecorder {
     }
 }
 
+/// Components of the delay between mixing a frame and hearing it, in seconds.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LatencyBreakdown {
+    /// Delay from the audio callback to playback as reported by the backend,
+    /// see [`AudioManager::estimate_latency`].
+    pub device: f32,
+    /// Length of the last block the mixer rendered. Everything in a block is
+    /// mixed at the start of the callback, so the last frame leaves up to this
+    /// much later.
+    pub buffer: f32,
+    /// Frames rendered ahead by the fixed-rate resampler and the fixed block
+    /// size, see [`AudioManagerBuilder::fixed_sample_rate`] and
+    /// [`AudioManagerBuilder::block_size`]. Zero without them.
+    pub internal: f32,
+}
+impl LatencyBreakdown {
+    pub fn total(&self) -> f32 {
+        self.device + self.buffer + self.internal
+    }
+}
+
 pub struct AudioManager {
     backend: Box<dyn Backend>,
     latency: Arc<AtomicU32>,
+    mixer_state: Arc<MixerState>,
     prod: HeapProducer<MixerCommand>,
+    max_renderers: Option<usize>,
+    restart_fade: f32,
+    stop_fade: f32,
 }
 
-impl AudioManager {
-    pub fn new(backend: impl Backend + 'static) -> Result<Self> {
-        Self::new_box(Box::new(backend))
+pub struct AudioManagerBuilder {
+    backend: Option<Box<dyn Backend>>,
+    command_buffer: usize,
+    latency_window: usize,
+    master_volume: f32,
+    restart_fade: f32,
+    stop_fade: f32,
+    max_renderers: Option<usize>,
+    renderer_capacity: usize,
+    block_size: usize,
+    fixed_sample_rate: Option<(u32, Resampler)>,
+    mono_downmix: MonoDownmix,
+    dc_blocker: bool,
+    removal_fade: f32,
+}
+
+impl Default for AudioManagerBuilder {
+    fn default() -> Self {
+        Self {
+            backend: None,
+            command_buffer: 16,
+            latency_window: DEFAULT_LATENCY_RECORD_NUM,
+            master_volume: 1.,
+            restart_fade: 0.005,
+            stop_fade: 0.005,
+            max_renderers: None,
+            renderer_capacity: 64,
+            block_size: 0,
+            fixed_sample_rate: None,
+            mono_downmix: MonoDownmix::default(),
+            dc_blocker: false,
+            removal_fade: 0.,
+        }
     }
+}
 
-    pub fn new_box(mut backend: Box<dyn Backend>) -> Result<Self> {
-        let (prod, cons) = HeapRb::new(16).split();
+impl AudioManagerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn backend(self, backend: impl Backend + 'static) -> Self {
+        self.backend_box(Box::new(backend))
+    }
+
+    pub fn backend_box(mut self, backend: Box<dyn Backend>) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    /// Capacity of the command ring between the manager and the mixer.
+    pub fn command_buffer(mut self, size: usize) -> Self {
+        self.command_buffer = size;
+        self
+    }
+
+    /// Number of latency records averaged by [`AudioManager::estimate_latency`].
+    pub fn latency_window(mut self, window: usize) -> Self {
+        self.latency_window = window;
+        self
+    }
+
+    pub fn master_volume(mut self, volume: f32) -> Self {
+        self.master_volume = volume;
+        self
+    }
+
+    /// Length in seconds of the fades around the backend (re)starting: the
+    /// output ramps down before [`AudioManager::stop`] or a restart of a
+    /// running stream, and back up once it starts, hiding the clicks. Zero
+    /// disables them.
+    pub fn restart_fade(mut self, time: f32) -> Self {
+        self.restart_fade = time;
+        self
+    }
+
+    /// Length in seconds of the fade-out given to voices cut short by
+    /// [`Sfx::stop_all`], so that stopping a sound doesn't click. Zero stops
+    /// them on the spot.
+    pub fn stop_fade(mut self, time: f32) -> Self {
+        self.stop_fade = time;
+        self
+    }
+
+    /// Soft cap on the number of renderers, past which
+    /// [`AudioManager::add_renderer`] fails instead of overloading the callback.
+    pub fn max_renderers(mut self, max: usize) -> Self {
+        self.max_renderers = Some(max);
+        self
+    }
+
+    /// Number of renderers the mixer has room for up front, so that adding
+    /// them never reallocates on the audio thread. Going beyond it still
+    /// works, but grows the storage in the callback. Defaults to 64.
+    ///
+    /// The storage is sized to [`AudioManagerBuilder::max_renderers`] if
+    /// that's larger, making the cap a hard real-time guarantee.
+    pub fn renderer_capacity(mut self, capacity: usize) -> Self {
+        self.renderer_capacity = capacity;
+        self
+    }
+
+    /// Has renderers process exactly `frames` frames at a time, whatever size
+    /// the backend's callbacks are, for effects that work on whole blocks.
+    /// Frames rendered ahead are kept for the next callback, adding up to one
+    /// block of latency. Zero (the default) renders what each callback asks for.
+    pub fn block_size(mut self, frames: usize) -> Self {
+        self.block_size = frames;
+        self
+    }
+
+    /// Runs all renderers at `sample_rate` whatever the device negotiates, and
+    /// converts the mix to the output rate with `resampler`. Assets can then be
+    /// prepared for a single rate, at the cost of resampling every block.
+    pub fn fixed_sample_rate(mut self, sample_rate: u32, resampler: Resampler) -> Self {
+        self.fixed_sample_rate = Some((sample_rate, resampler));
+        self
+    }
+
+    /// How stereo is folded down when the output is mono.
+    pub fn mono_downmix(mut self, downmix: MonoDownmix) -> Self {
+        self.mono_downmix = downmix;
+        self
+    }
+
+    /// Removes DC offset from the master output with a high-pass at a few Hz.
+    pub fn dc_blocker(mut self, enabled: bool) -> Self {
+        self.dc_blocker = enabled;
+        self
+    }
+
+    /// Length in seconds of a ramp the mixer appends to renderers that stop
+    /// on a non-zero sample, like a clip that doesn't end in silence, so they
+    /// don't click. This renders each renderer into its own buffer, so it's
+    /// off (zero) by default.
+    pub fn removal_fade(mut self, time: f32) -> Self {
+        self.removal_fade = time;
+        self
+    }
+
+    pub fn build(self) -> Result<AudioManager> {
+        let mut backend = self
+            .backend
+            .ok_or_else(|| SasaError::InvalidParameter("no backend is specified".to_owned()))?;
+        if self.command_buffer == 0 {
+            bail!(SasaError::InvalidParameter(
+                "command buffer size must be positive".to_owned()
+            ));
+        }
+        if let Some((0, _)) = self.fixed_sample_rate {
+            bail!(SasaError::InvalidParameter(
+                "fixed sample rate must be positive".to_owned()
+            ));
+        }
+        let (prod, cons) = HeapRb::new(self.command_buffer).split();
         let latency = Arc::default();
-        let latency_rec = LatencyRecorder::new(Arc::clone(&latency));
-        backend.setup(BackendSetup {
-            mixer_cons: cons,
-            latency_rec,
-        })?;
-        backend.start()?;
-        Ok(Self {
+        let latency_rec = LatencyRecorder::new(self.latency_window, Arc::clone(&latency));
+        let mixer_state = Arc::default();
+        let mut mixer = Mixer::new(0, cons, Arc::clone(&mixer_state));
+        mixer.master_volume = self.master_volume;
+        mixer.restart_fade = self.restart_fade;
+        mixer.mono_downmix = self.mono_downmix;
+        mixer.dc_blocker = self.dc_blocker;
+        mixer.removal_fade = self.removal_fade;
+        mixer.set_block_size(self.block_size);
+        mixer.reserve_renderers(self.renderer_capacity.max(self.max_renderers.unwrap_or(0)));
+        if let Some((sample_rate, resampler)) = self.fixed_sample_rate {
+            mixer.set_fixed_sample_rate(sample_rate, resampler);
+        }
+        backend.setup(BackendSetup { mixer, latency_rec })?;
+        let mut manager = AudioManager {
             backend,
             latency,
+            mixer_state,
             prod,
-        })
+            max_renderers: self.max_renderers,
+            restart_fade: self.restart_fade,
+            stop_fade: self.stop_fade,
+        };
+        manager.start()?;
+        Ok(manager)
+    }
+}
+
+impl AudioManager {
+    pub fn builder() -> AudioManagerBuilder {
+        AudioManagerBuilder::new()
+    }
+
+    pub fn new(backend: impl Backend + 'static) -> Result<Self> {
+        Self::new_box(Box::new(backend))
+    }
+
+    pub fn new_box(backend: Box<dyn Backend>) -> Result<Self> {
+        AudioManagerBuilder::new().backend_box(backend).build()
+    }
+
+    /// Tries `candidates` in order, e.g. an exclusive-mode backend before a
+    /// shared one, keeping the first that starts. Returns the manager along
+    /// with the index of the backend chosen.
+    pub fn new_with_fallback(
+        candidates: impl IntoIterator<Item = Box<dyn Backend>>,
+    ) -> Result<(Self, usize)> {
+        let mut errors = Vec::new();
+        for (index, backend) in candidates.into_iter().enumerate() {
+            match Self::new_box(backend) {
+                Ok(manager) => return Ok((manager, index)),
+                Err(err) => errors.push(format!("backend {index}: {err:#}")),
+            }
+        }
+        if errors.is_empty() {
+            bail!(SasaError::InvalidParameter(
+                "no backend to fall back to".to_owned()
+            ));
+        }
+        bail!(SasaError::DeviceUnavailable(format!(
+            "all backends failed to start:\n{}",
+            errors.join("\n")
+        )))
     }
 
+    /// Fails without returning a handle if the renderer can't be added (see
+    /// [`AudioManager::add_renderer`]), so a handle is never silently dead.
     pub fn create_sfx(&mut self, clip: AudioClip, buffer_size: Option<usize>) -> Result<Sfx> {
-        let (sfx, sfx_renderer) = Sfx::new(clip, buffer_size);
-        self.add_renderer(sfx_renderer)?;
+        let (sfx, sfx_renderer) = Sfx::new(clip, buffer_size, self.stop_fade);
+        self.add_renderer(sfx_renderer).context("create sfx")?;
         Ok(sfx)
     }
 
+    /// Plays `clip` once without keeping an [`Sfx`] around, for sounds that
+    /// never need to be controlled. The renderer removes itself when it ends.
+    pub fn play_one_shot(&mut self, clip: AudioClip, params: PlaySfxParams) -> Result<()> {
+        renderer::validate_pan(params.pan).context("play one shot")?;
+        self.add_renderer(Sfx::one_shot(clip, params))
+            .context("play one shot")
+    }
+
+    /// Fails without returning a handle if the renderer can't be added (see
+    /// [`AudioManager::add_renderer`]), so a handle is never silently dead.
+    pub fn create_sfx_pool(&mut self, clip: AudioClip, params: SfxPoolParams) -> Result<SfxPool> {
+        renderer::validate_pan(params.pan).context("create sfx pool")?;
+        let (pool, renderer) = SfxPool::new(clip, params);
+        self.add_renderer(renderer).context("create sfx pool")?;
+        Ok(pool)
+    }
+
+    /// Fails without returning a handle if the renderer can't be added (see
+    /// [`AudioManager::add_renderer`]), so a handle is never silently dead.
     pub fn create_music(&mut self, clip: AudioClip, settings: MusicParams) -> Result<Music> {
         let (music, music_renderer) = Music::new(clip, settings);
-        self.add_renderer(music_renderer)?;
+        self.add_renderer(music_renderer).context("create music")?;
         Ok(music)
     }
 
+    /// Starts playing `clip` on a seamless loop, e.g. for ambient sounds. The
+    /// returned handle controls it like any other music.
+    pub fn play_loop(&mut self, clip: AudioClip) -> Result<Music> {
+        let mut music = self.create_music(
+            clip,
+            MusicParams {
+                loop_mode: Some(LoopMode::Seamless),
+                ..Default::default()
+            },
+        )?;
+        music.play()?;
+        Ok(music)
+    }
+
+    /// Plays `[start, end]` seconds of `clip` once, fading in and out over
+    /// `fade` seconds at the edges, as for a track preview in a menu.
+    pub fn play_preview(
+        &mut self,
+        clip: AudioClip,
+        start: f32,
+        end: f32,
+        fade: f32,
+    ) -> Result<Music> {
+        if !(start.is_finite() && start < end) {
+            bail!(SasaError::InvalidParameter(format!(
+                "preview must end after it starts, got [{start}, {end}]"
+            )));
+        }
+        let mut music = self.create_music(clip, MusicParams::default())?;
+        music.seek_to(start)?;
+        // Leave room for the fade in when the section is short
+        music.set_stop_at(Some(end), fade.min((end - start) / 2.))?;
+        music.fade_in(fade)?;
+        Ok(music)
+    }
+
+    pub fn create_metronome(&mut self, params: MetronomeParams) -> Result<Metronome> {
+        let (metronome, renderer) = Metronome::new(params);
+        self.add_renderer(renderer).context("create metronome")?;
+        Ok(metronome)
+    }
+
+    /// Schedules a single full-scale sample at frame `sample` of
+    /// [`AudioManager::frames_rendered`]. The returned handle reports the frame
+    /// it was actually rendered at, which together with
+    /// [`AudioManager::total_latency`] tells when it leaves the device.
+    pub fn emit_impulse_at(&mut self, sample: u64) -> Result<Impulse> {
+        let (impulse, renderer) = Impulse::new(sample);
+        self.add_renderer(renderer).context("emit impulse")?;
+        Ok(impulse)
+    }
+
+    /// Plays a `frequency` Hz tone for `duration` seconds.
+    pub fn play_tone(&mut self, frequency: f32, duration: f32, params: ToneParams) -> Result<()> {
+        self.add_renderer(Oscillator::new(frequency, Some(duration), params))
+            .context("play tone")
+    }
+
+    /// Adds `renderer` to the mix. Renderers are always summed in the order
+    /// they were added, so a fixed sequence of calls renders bit-identically.
+    ///
+    /// Fails, dropping `renderer`, if the command buffer is full: the mixer
+    /// drains it once per audio callback, so bursts larger than
+    /// [`AudioManagerBuilder::command_buffer`] have to be spread out or the
+    /// buffer enlarged.
     pub fn add_renderer(&mut self, renderer: impl Renderer + 'static) -> Result<()> {
+        let pending = &self.mixer_state.pending_renderers;
+        if let Some(max) = self.max_renderers {
+            // Renderers the mixer hasn't picked up yet count as well
+            if self.active_renderers() + pending.load(Ordering::SeqCst) >= max {
+                bail!(SasaError::TooManyRenderers(max));
+            }
+        }
+        // Counted before the push, so that the mixer never takes it below zero
+        pending.fetch_add(1, Ordering::SeqCst);
         self.prod
             .push(MixerCommand::AddRenderer(Box::new(renderer)))
+            .map_err(|err| {
+                pending.fetch_sub(1, Ordering::SeqCst);
+                buffer_is_full(err)
+            })
+            .with_context(|| {
+                format!(
+                    "mixer command buffer is full (capacity {}), the renderer was not added",
+                    self.prod.capacity()
+                )
+            })
+    }
+
+    pub fn latency_breakdown(&self) -> LatencyBreakdown {
+        let load = |it: &AtomicU32| f32::from_bits(it.load(Ordering::SeqCst));
+        LatencyBreakdown {
+            device: self.estimate_latency(),
+            buffer: load(&self.mixer_state.block_latency),
+            internal: load(&self.mixer_state.internal_latency),
+        }
+    }
+
+    /// Sum of [`AudioManager::latency_breakdown`], for aligning input timing
+    /// with what is heard.
+    pub fn total_latency(&self) -> f32 {
+        self.latency_breakdown().total()
+    }
+
+    /// Channel count the backend renders with, e.g. 1 on a phone earpiece, for
+    /// deciding whether hard panning makes sense. Zero until the first audio
+    /// callback, and updated if a restarted stream changes it.
+    pub fn output_channels(&self) -> u16 {
+        self.mixer_state.output_channels.load(Ordering::SeqCst)
+    }
+
+    /// Number of renderers the mixer processed in the last audio callback.
+    pub fn active_renderers(&self) -> usize {
+        self.mixer_state.active_renderers.load(Ordering::SeqCst)
+    }
+
+    /// Per-renderer diagnostics for debug overlays.
+    ///
+    /// The audio thread never hands out its renderer list. Instead, each call
+    /// asks the mixer to describe the renderers during its next block and
+    /// returns the snapshot from the last block that did so, in mixing order.
+    /// Polled every frame, this lags by about one audio callback. The first
+    /// call returns an empty list.
+    pub fn debug_renderers(&self) -> Vec<RendererInfo> {
+        self.mixer_state
+            .debug_requested
+            .store(true, Ordering::SeqCst);
+        // A renderer panicking mid-snapshot only poisons diagnostics
+        self.mixer_state
+            .debug
+            .lock()
+            .map(|it| it.clone())
+            .unwrap_or_default()
+    }
+
+    /// Registers `callback` to be called for every block the mixer renders,
+    /// with the value of [`AudioManager::frames_rendered`] at the start of the
+    /// block and the number of frames in it. Replaces any previous callback.
+    ///
+    /// The callback runs on the audio thread, so it must not block or allocate.
+    /// Publishing into an atomic the game thread reads is the intended use.
+    pub fn set_block_callback(
+        &mut self,
+        callback: impl FnMut(u64, usize) + Send + Sync + 'static,
+    ) -> Result<()> {
+        self.prod
+            .push(MixerCommand::SetBlockCallback(Some(Box::new(callback))))
+            .map_err(buffer_is_full)
+            .context("set block callback")
+    }
+
+    pub fn clear_block_callback(&mut self) -> Result<()> {
+        self.prod
+            .push(MixerCommand::SetBlockCallback(None))
             .map_err(buffer_is_full)
-            .context("add renderer")?;
-        Ok(())
+            .context("clear block callback")
     }
 
     pub fn estimate_latency(&self) -> f32 {
         f32::from_bits(self.latency.load(Ordering::SeqCst))
     }
 
+    /// Number of frames the mixer has rendered since the manager was created.
+    ///
+    /// Divided by [`AudioManager::sample_rate`], this gives an audio clock that
+    /// doesn't drift with the game's frame rate.
+    pub fn frames_rendered(&self) -> u64 {
+        self.mixer_state.frames_rendered.load(Ordering::SeqCst)
+    }
+
+    /// The sample rate renderers run at. This is the output sample rate reported
+    /// by the backend (0 if it isn't known yet), unless
+    /// [`AudioManagerBuilder::fixed_sample_rate`] is set.
+    pub fn sample_rate(&self) -> u32 {
+        self.mixer_state.sample_rate.load(Ordering::SeqCst)
+    }
+
+    /// Whether `clip` will be resampled on the fly when played at the current
+    /// output sample rate. Heavy tracks can be converted ahead of time with
+    /// [`AudioClip::resampled`] instead.
+    pub fn would_resample(&self, clip: &AudioClip) -> bool {
+        clip.sample_rate() != self.sample_rate()
+    }
+
     #[inline(always)]
     pub fn consume_broken(&self) -> bool {
         self.backend.consume_broken()
     }
 
-    #[inline(always)]
+    pub fn backend_state(&self) -> BackendState {
+        self.backend.state()
+    }
+
+    /// Whether the backend's stream is live.
+    pub fn is_running(&self) -> bool {
+        self.backend.is_running()
+    }
+
+    /// Starts the backend, or restarts it, e.g. on another device.
+    ///
+    /// A running stream is faded out first, which blocks the calling thread
+    /// for about [`AudioManagerBuilder::restart_fade`].
     pub fn start(&mut self) -> Result<()> {
+        self.fade_out();
+        self.restart()
+    }
+
+    /// Starts the backend right away, fading the output in from silence.
+    fn restart(&mut self) -> Result<()> {
+        self.mixer_state.request_fade(FadeRequest::In);
         self.backend.start()
     }
 
+    /// Fades the output out over [`AudioManagerBuilder::restart_fade`], then
+    /// stops the backend until the next [`AudioManager::start`]. This blocks
+    /// the calling thread until the fade is done.
+    pub fn stop(&mut self) -> Result<()> {
+        self.fade_out();
+        self.backend.stop()
+    }
+
+    /// Blocks until the mixer has faded to silence, if the stream is running
+    /// to get there at all.
+    fn fade_out(&mut self) {
+        if !self.backend.is_running() {
+            return;
+        }
+        let faded_out = &self.mixer_state.faded_out;
+        faded_out.store(false, Ordering::SeqCst);
+        self.mixer_state.request_fade(FadeRequest::Out);
+        // Callbacks may stall without the stream being reported broken, so
+        // this gives up after a few of them
+        let deadline = Instant::now()
+            + Duration::from_secs_f32(self.restart_fade)
+            + Duration::from_millis(200);
+        while !faded_out.load(Ordering::SeqCst) && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Restarts the backend if its device is gone, or if it broke and its
+    /// stream stopped. A stream that broke but keeps running, as after a jack
+    /// xrun, is left alone since restarting it would only cause a longer
+    /// dropout, and so is an interrupted one the system is going to resume.
+    ///
+    /// Unlike [`AudioManager::start`] this never blocks on a fade, the stream
+    /// being restarted isn't playing anyway, so it's fine to call every frame.
     pub fn recover_if_needed(&mut self) -> Result<()> {
-        if self.consume_broken() {
-            self.start()
-        } else {
+        // Consumed either way, so that the restart doesn't trigger another one
+        let broken = self.consume_broken();
+        match self.backend_state() {
+            BackendState::Interrupted => Ok(()),
+            BackendState::Disconnected => self.restart(),
+            _ if broken && !self.backend.is_running() => self.restart(),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        cell::RefCell,
+        rc::Rc,
+        sync::{atomic::AtomicBool, Mutex},
+        thread::JoinHandle,
+    };
+
+    /// A backend without a device, leaving the mixer to the test to drive.
+    #[derive(Default)]
+    struct TestBackend {
+        setup: Rc<RefCell<Option<BackendSetup>>>,
+    }
+
+    impl Backend for TestBackend {
+        fn setup(&mut self, setup: BackendSetup) -> Result<()> {
+            *self.setup.borrow_mut() = Some(setup);
+            Ok(())
+        }
+
+        fn start(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn consume_broken(&self) -> bool {
+            false
+        }
+    }
+
+    /// Renders from its own thread while started, like a device would.
+    #[derive(Default)]
+    struct ThreadBackend {
+        setup: Arc<Mutex<Option<BackendSetup>>>,
+        output: Arc<Mutex<Vec<f32>>>,
+        running: Arc<AtomicBool>,
+        thread: Option<JoinHandle<()>>,
+    }
+
+    impl Backend for ThreadBackend {
+        fn setup(&mut self, mut setup: BackendSetup) -> Result<()> {
+            setup.set_sample_rate(48000);
+            *self.setup.lock().unwrap() = Some(setup);
+            Ok(())
+        }
+
+        fn start(&mut self) -> Result<()> {
+            self.stop()?;
+            self.running.store(true, Ordering::SeqCst);
+            let setup = Arc::clone(&self.setup);
+            let output = Arc::clone(&self.output);
+            let running = Arc::clone(&self.running);
+            self.thread = Some(std::thread::spawn(move || {
+                let mut data = [0.; 128];
+                while running.load(Ordering::SeqCst) {
+                    setup.lock().unwrap().as_mut().unwrap().render(2, &mut data);
+                    output.lock().unwrap().extend_from_slice(&data);
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+            }));
             Ok(())
         }
+
+        fn stop(&mut self) -> Result<()> {
+            self.running.store(false, Ordering::SeqCst);
+            if let Some(thread) = self.thread.take() {
+                thread.join().unwrap();
+            }
+            Ok(())
+        }
+
+        fn consume_broken(&self) -> bool {
+            false
+        }
+
+        fn is_running(&self) -> bool {
+            self.thread.is_some()
+        }
+    }
+
+    /// Counts its starts, leaving its status and whether it runs to the test.
+    #[derive(Default)]
+    struct StatusBackend {
+        status: Arc<backend::BackendStatus>,
+        starts: Rc<RefCell<usize>>,
+        running: Rc<RefCell<bool>>,
+    }
+
+    impl Backend for StatusBackend {
+        fn setup(&mut self, _setup: BackendSetup) -> Result<()> {
+            Ok(())
+        }
+
+        fn start(&mut self) -> Result<()> {
+            *self.starts.borrow_mut() += 1;
+            self.status.set_running();
+            Ok(())
+        }
+
+        fn consume_broken(&self) -> bool {
+            self.status.consume_broken()
+        }
+
+        fn state(&self) -> BackendState {
+            self.status.state()
+        }
+
+        fn is_running(&self) -> bool {
+            *self.running.borrow()
+        }
+    }
+
+    /// Waits until `output` holds `samples` samples, or a second passed.
+    fn wait_for_output(output: &Mutex<Vec<f32>>, samples: usize) {
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while output.lock().unwrap().len() < samples && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn start_fades_in_despite_a_full_command_ring() {
+        let backend = ThreadBackend::default();
+        let output = Arc::clone(&backend.output);
+        let mut manager = AudioManager::new(backend).unwrap();
+        let params = ToneParams {
+            waveform: Waveform::Square,
+            amplifier: 0.5,
+        };
+        manager
+            .add_renderer(Oscillator::new(0.1, None, params))
+            .unwrap();
+        wait_for_output(&output, 48000 / 20);
+        manager.stop().unwrap();
+        // Nothing drains the ring while the backend is stopped
+        while manager
+            .add_renderer(Noise::new(None, NoiseParams::default()))
+            .is_ok()
+        {}
+
+        let stopped = output.lock().unwrap().len();
+        manager.start().unwrap();
+        wait_for_output(&output, stopped + 48000 / 10);
+        manager.stop().unwrap();
+        let output = output.lock().unwrap();
+        assert!(output[stopped..].iter().any(|&it| it != 0.));
+    }
+
+    #[test]
+    fn stop_fades_out_the_running_stream() {
+        let backend = ThreadBackend::default();
+        let output = Arc::clone(&backend.output);
+        let mut manager = AudioManager::new(backend).unwrap();
+        let params = ToneParams {
+            waveform: Waveform::Square,
+            amplifier: 0.5,
+        };
+        // Stays high for the whole test
+        manager
+            .add_renderer(Oscillator::new(0.1, None, params))
+            .unwrap();
+        wait_for_output(&output, 48000 / 10);
+        manager.stop().unwrap();
+        assert!(!manager.is_running());
+
+        let left: Vec<f32> = output.lock().unwrap().iter().step_by(2).copied().collect();
+        let full = left.iter().copied().fold(0., f32::max);
+        assert!(full > 0.);
+        let tail = &left[left.iter().rposition(|&it| it == full).unwrap()..];
+        // The default fade is 240 frames
+        assert!(tail.len() > 240);
+        assert_eq!(tail[tail.len() - 1], 0.);
+        for pair in tail.windows(2) {
+            assert!(pair[1] <= pair[0] && pair[0] - pair[1] <= full / 240. * 1.01);
+        }
+    }
+
+    #[test]
+    fn recover_leaves_interrupted_streams_alone() {
+        let backend = StatusBackend::default();
+        let status = Arc::clone(&backend.status);
+        let starts = Rc::clone(&backend.starts);
+        let mut manager = AudioManager::new(backend).unwrap();
+        assert_eq!(*starts.borrow(), 1);
+
+        status.set_broken(BackendState::Interrupted);
+        manager.recover_if_needed().unwrap();
+        assert_eq!(*starts.borrow(), 1);
+        assert_eq!(manager.backend_state(), BackendState::Interrupted);
+
+        status.set_broken(BackendState::Disconnected);
+        manager.recover_if_needed().unwrap();
+        assert_eq!(*starts.borrow(), 2);
+        assert_eq!(manager.backend_state(), BackendState::Running);
+        manager.recover_if_needed().unwrap();
+        assert_eq!(*starts.borrow(), 2);
+    }
+
+    #[test]
+    fn recover_skips_streams_that_keep_running() {
+        let backend = StatusBackend::default();
+        let status = Arc::clone(&backend.status);
+        let starts = Rc::clone(&backend.starts);
+        let running = Rc::clone(&backend.running);
+        let mut manager = AudioManager::new(backend).unwrap();
+
+        // Like a jack xrun
+        *running.borrow_mut() = true;
+        status.set_broken(BackendState::Running);
+        manager.recover_if_needed().unwrap();
+        assert_eq!(*starts.borrow(), 1);
+
+        *running.borrow_mut() = false;
+        status.set_broken(BackendState::Running);
+        manager.recover_if_needed().unwrap();
+        assert_eq!(*starts.borrow(), 2);
+    }
+
+    #[test]
+    fn full_command_ring_rejects_renderers() {
+        let mut manager = AudioManager::builder()
+            .backend(TestBackend::default())
+            .command_buffer(4)
+            .build()
+            .unwrap();
+        for _ in 0..4 {
+            manager
+                .add_renderer(Noise::new(None, NoiseParams::default()))
+                .unwrap();
+        }
+        let err = manager
+            .add_renderer(Noise::new(None, NoiseParams::default()))
+            .unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(SasaError::BufferFull)));
+        assert!(format!("{err:#}").contains("the renderer was not added"));
+    }
+
+    #[test]
+    fn builder_rejects_degenerate_settings() {
+        let err = AudioManager::builder()
+            .backend(TestBackend::default())
+            .command_buffer(0)
+            .build()
+            .err()
+            .unwrap();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(SasaError::InvalidParameter(_))
+        ));
+        let err = AudioManager::builder()
+            .backend(TestBackend::default())
+            .fixed_sample_rate(0, Resampler::Linear)
+            .build()
+            .err()
+            .unwrap();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(SasaError::InvalidParameter(_))
+        ));
+    }
+
+    #[test]
+    fn renderer_cap_ignores_other_commands() {
+        let setup = Rc::default();
+        let mut manager = AudioManager::builder()
+            .backend(TestBackend {
+                setup: Rc::clone(&setup),
+            })
+            .max_renderers(2)
+            .build()
+            .unwrap();
+        let noise = || Noise::new(None, NoiseParams::default());
+        // Sits in the ring next to the start fade-in
+        manager.set_block_callback(|_, _| {}).unwrap();
+        manager.add_renderer(noise()).unwrap();
+        manager.add_renderer(noise()).unwrap();
+        let err = manager.add_renderer(noise()).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(SasaError::TooManyRenderers(2))
+        ));
+
+        let mut setup = setup.borrow_mut();
+        let setup = setup.as_mut().unwrap();
+        setup.set_sample_rate(48000);
+        setup.render(2, &mut [0.; 64]);
+        assert_eq!(manager.active_renderers(), 2);
+        assert!(manager.add_renderer(noise()).is_err());
     }
 }
@@ -1,51 +1,754 @@
+use crate::{flush_denormal, mix_frame, Frame, Renderer, Resampler};
 use ringbuf::HeapConsumer;
-use crate::Renderer;
+use std::sync::{
+    atomic::{AtomicBool, AtomicU16, AtomicU32, AtomicU64, AtomicU8, AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+
+pub(crate) type BlockCallback = Box<dyn FnMut(u64, usize) + Send + Sync>;
 
 pub(crate) enum MixerCommand {
     AddRenderer(Box<dyn Renderer>),
+    SetBlockCallback(Option<BlockCallback>),
+}
+
+/// Restart ramp requested through [`MixerState::fade_request`]. It's shared
+/// state rather than a command so that a full command ring can't lose it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub(crate) enum FadeRequest {
+    /// Nothing new, the output stays where the last ramp left it
+    None,
+    /// Ramp up from silence, requested whenever the backend (re)starts
+    In,
+    /// Ramp down to silence and hold it there until the next `In`, requested
+    /// before the backend stops
+    Out,
+}
+
+impl FadeRequest {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => FadeRequest::None,
+            1 => FadeRequest::In,
+            _ => FadeRequest::Out,
+        }
+    }
+}
+#[derive(Default)]
+pub(crate) struct MixerState {
+    pub frames_rendered: AtomicU64,
+    pub sample_rate: AtomicU32,
+    /// Channel count of the last block, zero before the first one
+    pub output_channels: AtomicU16,
+    pub active_renderers: AtomicUsize,
+    /// Renderers sent to the mixer that aren't in `active_renderers` yet
+    pub pending_renderers: AtomicUsize,
+    /// Length of the last output block in seconds, as f32 bits
+    pub block_latency: AtomicU32,
+    /// Delay added by the fixed-rate resampler and fixed block size in
+    /// seconds, as f32 bits
+    pub internal_latency: AtomicU32,
+    /// Set by the control thread to have the next block fill `debug`
+    pub debug_requested: AtomicBool,
+    pub debug: Mutex<Vec<RendererInfo>>,
+    /// A [`FadeRequest`], set by the control thread
+    pub fade_request: AtomicU8,
+    /// Set once a [`FadeRequest::Out`] has reached silence
+    pub faded_out: AtomicBool,
+}
+
+impl MixerState {
+    pub fn request_fade(&self, request: FadeRequest) {
+        self.fade_request.store(request as u8, Ordering::SeqCst);
+    }
+}
+
+/// A snapshot of one live renderer, see [`crate::AudioManager::debug_renderers`].
+#[derive(Debug, Clone)]
+pub struct RendererInfo {
+    pub name: &'static str,
+    /// Whether the renderer survived the block. Dead ones are dropped right after.
+    pub alive: bool,
+    /// Play head in seconds, for renderers that have one.
+    pub position: Option<f32>,
+    /// Largest absolute sample the renderer added to the block.
+    pub peak: f32,
+}
+
+/// How stereo renderers are folded into mono output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MonoDownmix {
+    #[default]
+    Average,
+    /// Only the left channel, for sources that leave the right one empty.
+    Left,
+    /// Both channels added together, which may clip.
+    Sum,
+}
+
+impl MonoDownmix {
+    #[inline(always)]
+    fn apply(self, frame: Frame) -> f32 {
+        match self {
+            MonoDownmix::Average => frame.avg(),
+            MonoDownmix::Left => frame.0,
+            MonoDownmix::Sum => frame.0 + frame.1,
+        }
+    }
+}
+
+/// Ramp from the last frame of a removed renderer down to silence.
+struct Tail {
+    frame: Frame,
+    /// Frame within the current block the ramp starts at
+    offset: usize,
+    remaining: u32,
+    total: u32,
+}
+
+/// At most this many tails play at once, so that they never allocate
+const MAX_TAILS: usize = 32;
+
+/// Renders in chunks of a fixed number of frames, whatever the callback asks for.
+struct FixedBlocks {
+    size: usize,
+    /// Channel layout of `buffer`, which is dropped when this changes
+    channels: u16,
+    /// The last chunk rendered, interleaved
+    buffer: Vec<f32>,
+    /// Frames of `buffer` already handed out
+    read: usize,
+}
+
+/// Renders at a fixed rate and resamples the result to the output rate.
+struct FixedRate {
+    sample_rate: u32,
+    resampler: Resampler,
+    /// Rendered frames not yet fully consumed, including some history
+    frames: Vec<Frame>,
+    /// Read position within `frames`
+    position: f64,
+    scratch: Vec<f32>,
 }
+
+impl FixedRate {
+    /// Frames the resampler needs around the read position.
+    fn reach(&self) -> usize {
+        match self.resampler {
+            Resampler::Nearest | Resampler::Linear => 1,
+            Resampler::Cubic => 2,
+            Resampler::Sinc(taps) => taps.max(1),
+        }
+    }
+}
+
 pub(crate) struct Mixer {
-    pub(crate) sample_rate: u32,
+    /// Output sample rate
+    sample_rate: u32,
+    pub(crate) master_volume: f32,
+    /// Length of the ramps applied on a [`FadeRequest`], in seconds
+    pub(crate) restart_fade: f32,
+    pub(crate) mono_downmix: MonoDownmix,
+    pub(crate) dc_blocker: bool,
+    /// Length of the ramp appended to removed renderers in seconds, zero to disable
+    pub(crate) removal_fade: f32,
 
+    fade_total: u32,
+    fade_current: u32,
+    /// Output level the ramp starts from and ends at, from 0 to 1
+    fade_from: f32,
+    fade_to: f32,
+    /// Frames rendered so far, the clock renderers are given. Mirrored to
+    /// [`MixerState::frames_rendered`].
+    frame: u64,
     renderers: Vec<Box<dyn Renderer>>,
+    /// Renderers taken from the ring since `active_renderers` was last stored
+    unpublished_renderers: usize,
+    block_callback: Option<BlockCallback>,
+    fixed: Option<FixedRate>,
+    blocks: Option<FixedBlocks>,
+    /// Stereo output rendered before a non-averaging mono downmix
+    mono_scratch: Vec<f32>,
+    /// Last input and output of the DC blocker, per channel
+    dc_state: Vec<(f32, f32)>,
+    /// Mix before each renderer, kept while taking a debug snapshot
+    debug_scratch: Vec<f32>,
+    /// Output of a single renderer, with `removal_fade` enabled
+    removal_scratch: Vec<f32>,
+    tails: Vec<Tail>,
     cons: HeapConsumer<MixerCommand>,
+    state: Arc<MixerState>,
 }
 
 impl Mixer {
-    pub(crate) fn new(sample_rate: u32, cons: HeapConsumer<MixerCommand>) -> Self {
+    pub(crate) fn new(
+        sample_rate: u32,
+        cons: HeapConsumer<MixerCommand>,
+        state: Arc<MixerState>,
+    ) -> Self {
+        state.sample_rate.store(sample_rate, Ordering::SeqCst);
         Self {
             sample_rate,
+            master_volume: 1.,
+            restart_fade: 0.,
+            mono_downmix: MonoDownmix::default(),
+            dc_blocker: false,
+            removal_fade: 0.,
 
+            fade_total: 0,
+            fade_current: 0,
+            fade_from: 1.,
+            fade_to: 1.,
+            frame: 0,
             renderers: Vec::new(),
+            unpublished_renderers: 0,
+            block_callback: None,
+            fixed: None,
+            blocks: None,
+            mono_scratch: Vec::new(),
+            dc_state: Vec::new(),
+            debug_scratch: Vec::new(),
+            removal_scratch: Vec::new(),
+            tails: Vec::with_capacity(MAX_TAILS),
             cons,
+            state,
+        }
+    }
+
+    /// Sample rate seen by renderers.
+    fn render_sample_rate(&self) -> u32 {
+        self.fixed
+            .as_ref()
+            .map_or(self.sample_rate, |it| it.sample_rate)
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        if self.sample_rate != sample_rate {
+            self.sample_rate = sample_rate;
+            self.state
+                .sample_rate
+                .store(self.render_sample_rate(), Ordering::SeqCst);
         }
     }
 
+    /// Makes renderers run at `sample_rate` regardless of the output's rate,
+    /// converting with `resampler`.
+    pub fn set_fixed_sample_rate(&mut self, sample_rate: u32, resampler: Resampler) {
+        self.fixed = Some(FixedRate {
+            sample_rate,
+            resampler,
+            frames: Vec::new(),
+            position: 0.,
+            scratch: Vec::new(),
+        });
+        self.state.sample_rate.store(sample_rate, Ordering::SeqCst);
+    }
+
+    /// Has renderers process exactly `size` frames at a time, buffering what
+    /// the callback doesn't take yet. Zero renders whatever the callback asks.
+    pub fn set_block_size(&mut self, size: usize) {
+        self.blocks = (size != 0).then(|| FixedBlocks {
+            size,
+            channels: 0,
+            buffer: Vec::new(),
+            read: size,
+        });
+    }
+
     fn consume_commands(&mut self) {
-        for cmd in self.cons.pop_iter() {
+        while let Some(cmd) = self.cons.pop() {
             match cmd {
-                MixerCommand::AddRenderer(renderer) => self.renderers.push(renderer),
+                MixerCommand::AddRenderer(renderer) => {
+                    self.renderers.push(renderer);
+                    self.unpublished_renderers += 1;
+                }
+                MixerCommand::SetBlockCallback(callback) => self.block_callback = callback,
+            }
+        }
+        let request = &self.state.fade_request;
+        match FadeRequest::from_u8(request.load(Ordering::SeqCst)) {
+            FadeRequest::None => {}
+            FadeRequest::In => {
+                // Left alone if the control thread asked for something else meanwhile
+                if request
+                    .compare_exchange(
+                        FadeRequest::In as u8,
+                        FadeRequest::None as u8,
+                        Ordering::SeqCst,
+                        Ordering::SeqCst,
+                    )
+                    .is_ok()
+                {
+                    self.start_ramp(0., 1.);
+                }
+            }
+            // Stays requested until the next fade in, ramping only once
+            FadeRequest::Out => {
+                if self.fade_to != 0. {
+                    self.start_ramp(self.fade_level(), 0.);
+                }
             }
         }
     }
 
-    pub fn render_mono(&mut self, data: &mut [f32]) {
-        self.consume_commands();
+    /// Makes room for `capacity` renderers, so that adding up to that many
+    /// never reallocates on the audio thread.
+    pub(crate) fn reserve_renderers(&mut self, capacity: usize) {
+        self.renderers
+            .reserve(capacity.saturating_sub(self.renderers.len()));
+    }
+
+    fn advance_frames(&mut self, frames: usize) {
+        self.frame += frames as u64;
+        self.state
+            .frames_rendered
+            .store(self.frame, Ordering::SeqCst);
+    }
+
+    fn start_ramp(&mut self, from: f32, to: f32) {
+        self.fade_total = (self.restart_fade * self.sample_rate as f32).round() as _;
+        self.fade_current = 0;
+        self.fade_from = from;
+        self.fade_to = to;
+    }
+
+    /// Current output level of the restart ramps, from 0 to 1.
+    fn fade_level(&self) -> f32 {
+        if self.fade_current < self.fade_total {
+            let progress = self.fade_current as f32 / self.fade_total as f32;
+            self.fade_from + (self.fade_to - self.fade_from) * progress
+        } else {
+            self.fade_to
+        }
+    }
+
+    fn apply_gain(&mut self, channels: usize, data: &mut [f32]) {
+        // Only the frames still within the ramp need a per-frame gain, the rest
+        // is a flat loop the compiler can vectorize
+        let ramp =
+            (self.fade_total.saturating_sub(self.fade_current) as usize).min(data.len() / channels);
+        let (ramp, rest) = data.split_at_mut(ramp * channels);
+        for frame in ramp.chunks_exact_mut(channels) {
+            self.fade_current += 1;
+            let gain = self.master_volume * self.fade_level();
+            for sample in frame {
+                *sample *= gain;
+            }
+        }
+        if self.fade_to == 0. {
+            rest.fill(0.);
+            if self.fade_current >= self.fade_total {
+                self.state.faded_out.store(true, Ordering::SeqCst);
+            }
+        } else if self.master_volume != 1. {
+            for sample in rest {
+                *sample *= self.master_volume;
+            }
+        }
+    }
+
+    fn block_dc(&mut self, channels: usize, data: &mut [f32]) {
+        const CUTOFF: f32 = 5.;
+        let r = (-std::f32::consts::TAU * CUTOFF / self.sample_rate as f32).exp();
+        self.dc_state.resize(channels, (0., 0.));
+        for frame in data.chunks_exact_mut(channels) {
+            for (sample, (x1, y1)) in frame.iter_mut().zip(&mut self.dc_state) {
+                let y = flush_denormal(*sample - *x1 + r * *y1);
+                *x1 = *sample;
+                *y1 = y;
+                *sample = y;
+            }
+        }
+    }
+
+    /// Runs all renderers over `data`.
+    fn render_renderers(&mut self, sample_rate: u32, channels: u16, data: &mut [f32]) {
         data.fill(0.);
+        if self.state.debug_requested.swap(false, Ordering::SeqCst) {
+            // Snapshots skip the removal fade, costing at most one click
+            self.render_debug(sample_rate, channels, data);
+        } else if self.removal_fade > 0. {
+            self.render_with_tails(sample_rate, channels, data);
+        } else {
+            // Renderers are summed in the order they were added, which `retain_mut`
+            // preserves as others die. The same commands thus give identical output.
+            let frame = self.frame;
+            self.renderers.retain_mut(|renderer| {
+                renderer.render_at(frame, sample_rate, channels, data);
+                renderer.alive()
+            });
+        }
+        self.state
+            .active_renderers
+            .store(self.renderers.len(), Ordering::SeqCst);
+        // Only now so that the control side never sees a new renderer in
+        // neither count
+        self.state.pending_renderers.fetch_sub(
+            std::mem::take(&mut self.unpublished_renderers),
+            Ordering::SeqCst,
+        );
+        let frames = data.len() / channels as usize;
+        if let Some(callback) = &mut self.block_callback {
+            callback(self.frame, frames);
+        }
+        self.advance_frames(frames);
+    }
 
+    /// Like the plain loop in [`Mixer::render_renderers`], but isolates every
+    /// renderer to find the last frame of the ones that die, continuing it as a
+    /// linear ramp to zero. That turns the step into a slope, which is enough
+    /// to hide the click.
+    fn render_with_tails(&mut self, sample_rate: u32, channels: u16, data: &mut [f32]) {
+        let channels = channels as usize;
+        let total = ((self.removal_fade * sample_rate as f32).round() as u32).max(1);
+        let frame = self.frame;
+        let scratch = &mut self.removal_scratch;
+        // Grows only until the block size settles
+        scratch.resize(data.len(), 0.);
+        let tails = &mut self.tails;
         self.renderers.retain_mut(|renderer| {
-            renderer.render_mono(self.sample_rate, data);
-            renderer.alive()
+            scratch.fill(0.);
+            renderer.render_at(frame, sample_rate, channels as u16, scratch);
+            for (out, sample) in data.iter_mut().zip(scratch.iter()) {
+                *out += sample;
+            }
+            let alive = renderer.alive();
+            if !alive && tails.len() < MAX_TAILS {
+                let last = scratch
+                    .chunks_exact(channels)
+                    .rposition(|frame| frame.iter().take(2).any(|&it| it != 0.));
+                if let Some(last) = last {
+                    let frame = &scratch[last * channels..];
+                    tails.push(Tail {
+                        frame: Frame(frame[0], frame[channels.min(2) - 1]),
+                        offset: last + 1,
+                        remaining: total,
+                        total,
+                    });
+                }
+            }
+            alive
         });
-    }
 
-    pub fn render_stereo(&mut self, data: &mut [f32]) {
-        self.consume_commands();
-        data.fill(0.);
+        self.tails.retain_mut(|tail| {
+            for out in data.chunks_exact_mut(channels).skip(tail.offset) {
+                if tail.remaining == 0 {
+                    break;
+                }
+                tail.remaining -= 1;
+                let frame = tail.frame * (tail.remaining as f32 / tail.total as f32);
+                if let [mono] = out {
+                    *mono += frame.0;
+                } else {
+                    mix_frame(out, frame);
+                }
+            }
+            tail.offset = 0;
+            tail.remaining != 0
+        });
+    }
 
+    /// Like the plain loop in [`Mixer::render_renderers`], but measures what
+    /// each renderer adds and publishes it. This copies the mix once per
+    /// renderer and may allocate, which is fine for the rare block it runs on.
+    fn render_debug(&mut self, sample_rate: u32, channels: u16, data: &mut [f32]) {
+        let Ok(mut debug) = self.state.debug.try_lock() else {
+            // The control thread is reading the last snapshot, try again next block
+            self.state.debug_requested.store(true, Ordering::SeqCst);
+            let frame = self.frame;
+            self.renderers.retain_mut(|renderer| {
+                renderer.render_at(frame, sample_rate, channels, data);
+                renderer.alive()
+            });
+            return;
+        };
+        debug.clear();
+        let frame = self.frame;
+        let scratch = &mut self.debug_scratch;
         self.renderers.retain_mut(|renderer| {
-            renderer.render_stereo(self.sample_rate, data);
-            renderer.alive()
+            scratch.clear();
+            scratch.extend_from_slice(data);
+            renderer.render_at(frame, sample_rate, channels, data);
+            let peak = data
+                .iter()
+                .zip(scratch.iter())
+                .fold(0f32, |peak, (after, before)| {
+                    peak.max((after - before).abs())
+                });
+            let alive = renderer.alive();
+            debug.push(RendererInfo {
+                name: renderer.name(),
+                alive,
+                position: renderer.position(),
+                peak,
+            });
+            alive
         });
     }
-}
\ No newline at end of file
+
+    /// Runs renderers over `data`, in fixed-size chunks if set up to.
+    fn render_chunked(&mut self, sample_rate: u32, channels: u16, data: &mut [f32]) {
+        let Some(mut blocks) = self.blocks.take() else {
+            self.render_renderers(sample_rate, channels, data);
+            return;
+        };
+        let size = blocks.size;
+        if blocks.channels != channels {
+            // Frames left over in another layout are lost, costing at most one click
+            blocks.channels = channels;
+            blocks.buffer.resize(size * channels as usize, 0.);
+            blocks.read = size;
+        }
+        let mut out = data;
+        while !out.is_empty() {
+            if blocks.read == size {
+                self.render_renderers(sample_rate, channels, &mut blocks.buffer);
+                blocks.read = 0;
+            }
+            let start = blocks.read * channels as usize;
+            let len = out.len().min(blocks.buffer.len() - start);
+            let (head, rest) = std::mem::take(&mut out).split_at_mut(len);
+            head.copy_from_slice(&blocks.buffer[start..start + len]);
+            blocks.read += len / channels as usize;
+            out = rest;
+        }
+        self.blocks = Some(blocks);
+    }
+
+    /// Returns the frames rendered ahead, in seconds.
+    fn render_fixed(&mut self, fixed: &mut FixedRate, channels: u16, data: &mut [f32]) -> f32 {
+        let ratio = fixed.sample_rate as f64 / self.sample_rate as f64;
+        let reach = fixed.reach();
+        let end = fixed.position + (data.len() / channels as usize) as f64 * ratio;
+        let needed = (end.ceil() as usize + reach).saturating_sub(fixed.frames.len());
+        if needed != 0 {
+            // Grows only until the block size settles
+            fixed.scratch.resize(needed * 2, 0.);
+            self.render_chunked(fixed.sample_rate, 2, &mut fixed.scratch);
+            fixed
+                .frames
+                .extend(fixed.scratch.chunks_exact(2).map(|it| Frame(it[0], it[1])));
+        }
+
+        data.fill(0.);
+        for out in data.chunks_exact_mut(channels as usize) {
+            let frame = fixed
+                .resampler
+                .sample(&fixed.frames, fixed.position)
+                .unwrap_or_default();
+            if let [mono] = out {
+                *mono = self.mono_downmix.apply(frame);
+            } else {
+                mix_frame(out, frame);
+            }
+            fixed.position += ratio;
+        }
+
+        // Drop what's been consumed, keeping enough history to interpolate
+        let consumed = (fixed.position as usize).saturating_sub(reach);
+        fixed.frames.drain(..consumed);
+        fixed.position -= consumed as f64;
+
+        // Rendered frames waiting for the next block
+        let pending = fixed.frames.len() as f64 - fixed.position;
+        (pending / fixed.sample_rate as f64) as f32
+    }
+
+    /// Renders interleaved output with `channels` channels into `data`.
+    pub fn render(&mut self, channels: u16, data: &mut [f32]) {
+        self.consume_commands();
+        self.state.output_channels.store(channels, Ordering::SeqCst);
+        let mut internal_latency = 0.;
+        if let Some(mut fixed) = self.fixed.take() {
+            if self.sample_rate == 0 {
+                data.fill(0.);
+            } else {
+                internal_latency = self.render_fixed(&mut fixed, channels, data);
+            }
+            self.fixed = Some(fixed);
+        } else if channels == 1 && self.mono_downmix != MonoDownmix::Average {
+            // Renderers average on their own, so anything else needs stereo first
+            let mut scratch = std::mem::take(&mut self.mono_scratch);
+            scratch.resize(data.len() * 2, 0.);
+            self.render_chunked(self.sample_rate, 2, &mut scratch);
+            for (mono, frame) in data.iter_mut().zip(scratch.chunks_exact(2)) {
+                *mono = self.mono_downmix.apply(Frame(frame[0], frame[1]));
+            }
+            self.mono_scratch = scratch;
+        } else {
+            self.render_chunked(self.sample_rate, channels, data);
+        }
+        if let Some(blocks) = &self.blocks {
+            let sample_rate = self.render_sample_rate();
+            if sample_rate != 0 {
+                internal_latency += (blocks.size - blocks.read) as f32 / sample_rate as f32;
+            }
+        }
+        self.state
+            .internal_latency
+            .store(internal_latency.to_bits(), Ordering::SeqCst);
+        self.apply_gain(channels as usize, data);
+        if self.sample_rate != 0 {
+            if self.dc_blocker {
+                self.block_dc(channels as usize, data);
+            }
+            let block = (data.len() / channels as usize) as f32 / self.sample_rate as f32;
+            self.state
+                .block_latency
+                .store(block.to_bits(), Ordering::SeqCst);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ringbuf::HeapRb;
+
+    /// A sine whose phase only depends on how many frames it has rendered.
+    struct Sine {
+        frequency: f64,
+        frame: u64,
+    }
+
+    impl Renderer for Sine {
+        fn alive(&self) -> bool {
+            true
+        }
+
+        fn render(&mut self, sample_rate: u32, channels: u16, data: &mut [f32]) {
+            for frame in data.chunks_exact_mut(channels as usize) {
+                let time = self.frame as f64 / sample_rate as f64;
+                let value = (time * self.frequency * std::f64::consts::TAU).sin() as f32;
+                for sample in frame {
+                    *sample += value;
+                }
+                self.frame += 1;
+            }
+        }
+    }
+
+    fn mixer_with_sine(sample_rate: u32, frequency: f64) -> (Mixer, Arc<MixerState>) {
+        let (mut prod, cons) = HeapRb::new(4).split();
+        let sine = Sine {
+            frequency,
+            frame: 0,
+        };
+        assert!(prod.push(MixerCommand::AddRenderer(Box::new(sine))).is_ok());
+        let state = Arc::<MixerState>::default();
+        (Mixer::new(sample_rate, cons, Arc::clone(&state)), state)
+    }
+
+    /// Renders one stereo callback per entry of `sizes`, returning the left channel.
+    fn render(mixer: &mut Mixer, sizes: &[usize]) -> Vec<f32> {
+        let mut out = Vec::new();
+        for &size in sizes {
+            let mut data = vec![0.; size * 2];
+            mixer.render(2, &mut data);
+            out.extend(data.chunks_exact(2).map(|it| it[0]));
+        }
+        out
+    }
+
+    #[test]
+    fn restart_fades_ramp_across_the_stop() {
+        const FADE: usize = 240;
+        let (mut prod, cons) = HeapRb::new(4).split();
+        let sine = Sine {
+            frequency: 10.,
+            frame: 0,
+        };
+        assert!(prod.push(MixerCommand::AddRenderer(Box::new(sine))).is_ok());
+        let state = Arc::<MixerState>::default();
+        let mut mixer = Mixer::new(48000, cons, Arc::clone(&state));
+        mixer.restart_fade = FADE as f32 / 48000.;
+        let before = render(&mut mixer, &[1000]);
+
+        state.request_fade(FadeRequest::Out);
+        let stopping = render(&mut mixer, &[100; 5]);
+        assert!(state.faded_out.load(Ordering::SeqCst));
+        // Picks up where the output was, then ramps down without a step
+        let max_step = 1. / FADE as f32 + std::f32::consts::TAU * 10. / 48000.;
+        let mut last = before[before.len() - 1];
+        for (i, &sample) in stopping.iter().enumerate() {
+            assert!((sample - last).abs() <= max_step * 1.01, "frame {i}");
+            if i + 1 >= FADE {
+                assert_eq!(sample, 0.);
+            }
+            last = sample;
+        }
+
+        state.request_fade(FadeRequest::In);
+        let starting = render(&mut mixer, &[500]);
+        for (i, &sample) in starting[..FADE].iter().enumerate() {
+            assert!(sample.abs() <= (i + 1) as f32 / FADE as f32, "frame {i}");
+        }
+        // Back at full level, with the sine as rendered
+        let frame = (1000 + 500 + 499) as f64 / 48000.;
+        let expected = (frame * 10. * std::f64::consts::TAU).sin() as f32;
+        assert!((starting[499] - expected).abs() < 1e-6);
+    }
+
+    #[test]
+    fn output_rate_change_keeps_fixed_rate_continuous() {
+        let (mut mixer, state) = mixer_with_sine(48000, 100.);
+        mixer.set_fixed_sample_rate(48000, Resampler::Linear);
+        let mut out = render(&mut mixer, &[256; 8]);
+        let before = state.frames_rendered.load(Ordering::SeqCst);
+
+        mixer.set_sample_rate(44100);
+        out.extend(render(&mut mixer, &[256; 40]));
+        let rendered = state.frames_rendered.load(Ordering::SeqCst) - before;
+        let expected = (256 * 40) as f64 * 48000. / 44100.;
+        assert!(
+            (rendered as f64 - expected).abs() < 4.,
+            "rendered {rendered} fixed-rate frames for {expected}"
+        );
+
+        // A 100 Hz sine moves at most this much per frame at 44.1 kHz
+        let max_step = (std::f32::consts::TAU * 100. / 44100.) * 1.01;
+        for pair in out.windows(2) {
+            assert!((pair[1] - pair[0]).abs() <= max_step, "{pair:?}");
+        }
+    }
+
+    #[test]
+    fn fixed_block_size_matches_unchunked() {
+        const BLOCK: usize = 64;
+        for fixed_rate in [None, Some(44100)] {
+            for len in [1, BLOCK - 1, BLOCK + 1] {
+                let sizes = vec![len; 4 * BLOCK / len + 3];
+                let (mut plain, _) = mixer_with_sine(48000, 440.);
+                let (mut chunked, _) = mixer_with_sine(48000, 440.);
+                if let Some(rate) = fixed_rate {
+                    plain.set_fixed_sample_rate(rate, Resampler::Linear);
+                    chunked.set_fixed_sample_rate(rate, Resampler::Linear);
+                }
+                chunked.set_block_size(BLOCK);
+                assert_eq!(
+                    render(&mut plain, &sizes),
+                    render(&mut chunked, &sizes),
+                    "callbacks of {len} frames, fixed rate {fixed_rate:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn fixed_block_size_is_continuous_across_mixed_callbacks() {
+        const BLOCK: usize = 64;
+        // Callbacks that straddle, split and skip over block boundaries
+        let sizes = [1, 37, 100, 3, 129, 64, 7, 63, 200, 5];
+        let (mut plain, _) = mixer_with_sine(48000, 440.);
+        let (mut chunked, state) = mixer_with_sine(48000, 440.);
+        chunked.set_block_size(BLOCK);
+        let out = render(&mut chunked, &sizes);
+        assert_eq!(render(&mut plain, &sizes), out);
+        assert_eq!(out.len(), sizes.iter().sum::<usize>());
+        // Whole blocks only, at most one of them rendered ahead
+        let rendered = state.frames_rendered.load(Ordering::SeqCst) as usize;
+        assert_eq!(rendered % BLOCK, 0);
+        assert!(rendered >= out.len() && rendered < out.len() + BLOCK);
+    }
+}
@@ -1,11 +1,168 @@
+use crate::Frame;
+
+mod delay;
+pub use delay::DelayParams;
+
+mod impulse;
+pub use impulse::Impulse;
+
+mod metronome;
+pub use metronome::{Metronome, MetronomeParams};
+
 mod music;
-pub use music::{Music, MusicParams};
+pub use music::{LoopMode, Music, MusicParams, MusicParamsBuilder, MusicStatus, MusicUpdate};
+
+mod noise;
+pub(crate) use noise::Rng;
+pub use noise::{Noise, NoiseColor, NoiseParams};
+
+mod pan;
+pub(crate) use pan::validate_pan;
+pub use pan::PanLaw;
+
+mod oscillator;
+pub use oscillator::{Oscillator, ToneParams, Waveform};
+
+mod radio;
+pub use radio::RadioParams;
 
 mod sfx;
-pub use sfx::{Sfx, PlaySfxParams};
+pub use sfx::{PlaySfxParams, Sfx};
+
+mod sfx_pool;
+pub use sfx_pool::{SfxPool, SfxPoolParams};
 
 pub trait Renderer: Send + Sync {
     fn alive(&self) -> bool;
-    fn render_mono(&mut self, sample_rate: u32, data: &mut [f32]);
-    fn render_stereo(&mut self, sample_rate: u32, data: &mut [f32]);
+
+    /// Adds `data.len() / channels` interleaved frames of output to `data`.
+    fn render(&mut self, sample_rate: u32, channels: u16, data: &mut [f32]);
+
+    /// Like [`Renderer::render`], knowing that the block starts at frame
+    /// `start_frame` of the mixer's clock (see
+    /// [`crate::AudioManager::frames_rendered`]). The mixer always calls this;
+    /// renderers that need sample-accurate timing override it.
+    #[inline]
+    fn render_at(&mut self, start_frame: u64, sample_rate: u32, channels: u16, data: &mut [f32]) {
+        let _ = start_frame;
+        self.render(sample_rate, channels, data);
+    }
+
+    #[inline]
+    fn render_mono(&mut self, sample_rate: u32, data: &mut [f32]) {
+        self.render(sample_rate, 1, data);
+    }
+
+    #[inline]
+    fn render_stereo(&mut self, sample_rate: u32, data: &mut [f32]) {
+        self.render(sample_rate, 2, data);
+    }
+
+    /// Name shown in [`crate::AudioManager::debug_renderers`].
+    fn name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+
+    /// Play head in seconds, for renderers that have one.
+    fn position(&self) -> Option<f32> {
+        None
+    }
+}
+
+/// Renders `frames` stereo frames of `renderer` at `sample_rate` into a
+/// fresh interleaved buffer, in a single block and without any backend.
+///
+/// Meant for tests, where it acts as a deterministic clock.
+pub fn render_frames(renderer: &mut dyn Renderer, sample_rate: u32, frames: usize) -> Vec<f32> {
+    let mut data = vec![0.; frames * 2];
+    renderer.render_stereo(sample_rate, &mut data);
+    data
+}
+
+/// Adds `frame` to a single interleaved output frame. Mono output gets the
+/// average of both channels, and channels beyond the first two are left
+/// untouched.
+#[inline(always)]
+pub fn mix_frame(out: &mut [f32], frame: Frame) {
+    match out {
+        [] => {}
+        [mono] => *mono += frame.avg(),
+        [left, right, ..] => {
+            *left += frame.0;
+            *right += frame.1;
+        }
+    }
+}
+
+/// Mixes frames from `next` into `data` (see [`mix_frame`]) until either runs
+/// out, returning the number of frames mixed.
+///
+/// The channel count is matched once up front so that the inner loops stay
+/// free of per-frame branching and bounds checks.
+#[inline(always)]
+pub(crate) fn mix_frames(
+    channels: u16,
+    data: &mut [f32],
+    mut next: impl FnMut() -> Option<Frame>,
+) -> usize {
+    let mut count = 0;
+    match channels {
+        0 => {}
+        1 => {
+            for mono in data {
+                let Some(frame) = next() else { break };
+                *mono += frame.avg();
+                count += 1;
+            }
+        }
+        2 => {
+            for out in data.chunks_exact_mut(2) {
+                let Some(frame) = next() else { break };
+                out[0] += frame.0;
+                out[1] += frame.1;
+                count += 1;
+            }
+        }
+        _ => {
+            for out in data.chunks_exact_mut(channels as usize) {
+                let Some(frame) = next() else { break };
+                mix_frame(out, frame);
+                count += 1;
+            }
+        }
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mix_frames_matches_mixing_frame_by_frame() {
+        let source: Vec<_> = (0..37)
+            .map(|i| Frame((i as f32 * 0.3).sin(), (i as f32 * 0.7).cos()))
+            .collect();
+        for channels in 1..=4u16 {
+            for frames in [20, 37, 50] {
+                let initial: Vec<f32> = (0..frames * channels as usize)
+                    .map(|i| i as f32 * 0.01)
+                    .collect();
+                let mut expected = initial.clone();
+                let mut count = 0;
+                for (out, &frame) in expected.chunks_exact_mut(channels as usize).zip(&source) {
+                    mix_frame(out, frame);
+                    count += 1;
+                }
+
+                let mut data = initial;
+                let mut iter = source.iter().copied();
+                assert_eq!(mix_frames(channels, &mut data, || iter.next()), count);
+                assert!(data
+                    .iter()
+                    .zip(&expected)
+                    .all(|(a, b)| a.to_bits() == b.to_bits()));
+            }
+        }
+    }
 }
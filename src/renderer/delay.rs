@@ -0,0 +1,157 @@
+use crate::Frame;
+
+/// An echo locked to a tempo, see [`crate::Music::set_delay`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DelayParams {
+    /// Delay time, in beats.
+    pub beats: f32,
+    pub bpm: f32,
+    /// Portion of each echo fed back into the line.
+    pub feedback: f32,
+    /// Level of the echoes mixed into the output.
+    pub mix: f32,
+}
+impl Default for DelayParams {
+    fn default() -> Self {
+        Self {
+            beats: 1.,
+            bpm: 120.,
+            feedback: 0.4,
+            mix: 0.5,
+        }
+    }
+}
+
+/// Highest output rate the line is sized for. Above it, echoes come early.
+const MAX_SAMPLE_RATE: u32 = 192000;
+
+/// Allocated in full up front, on the control side, so that neither a sample
+/// rate change nor a tempo change allocates on the audio thread.
+pub(crate) struct DelayLine {
+    params: DelayParams,
+    buffer: Vec<Frame>,
+    /// Frames of `buffer` in use at the current sample rate
+    len: usize,
+    head: usize,
+}
+
+impl DelayLine {
+    pub fn new(params: DelayParams) -> Self {
+        let buffer = vec![Frame::default(); Self::frames(&params, MAX_SAMPLE_RATE)];
+        Self {
+            params,
+            len: buffer.len(),
+            buffer,
+            head: 0,
+        }
+    }
+
+    fn frames(params: &DelayParams, sample_rate: u32) -> usize {
+        let len = (sample_rate as f32 * 60. / params.bpm * params.beats).round();
+        if len.is_finite() {
+            len.max(1.) as usize
+        } else {
+            1
+        }
+    }
+
+    /// Adapts the line's length to `sample_rate`, keeping the most recent
+    /// frames so that the echoes carry on.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        let len = Self::frames(&self.params, sample_rate).min(self.buffer.len());
+        if len == self.len {
+            return;
+        }
+        // Oldest frame first
+        self.buffer[..self.len].rotate_left(self.head);
+        self.head = 0;
+        if len < self.len {
+            self.buffer.copy_within(self.len - len..self.len, 0);
+        } else {
+            // Nothing was recorded that far back, so the new part starts silent
+            let extra = len - self.len;
+            self.buffer.copy_within(..self.len, extra);
+            self.buffer[..extra].fill(Frame::default());
+        }
+        self.len = len;
+    }
+
+    /// Carries the pending echoes of `old` over into this line, e.g. across a
+    /// tempo change. Both have to be at the same sample rate.
+    pub fn take_echoes(&mut self, old: &DelayLine) {
+        let count = self.len.min(old.len);
+        // The newest frame sits right before the head
+        self.head = 0;
+        self.buffer[..self.len - count].fill(Frame::default());
+        for (i, frame) in self.buffer[self.len - count..self.len]
+            .iter_mut()
+            .enumerate()
+        {
+            *frame = old.buffer[(old.head + old.len - count + i) % old.len];
+        }
+    }
+
+    #[inline]
+    pub fn process(&mut self, frame: Frame) -> Frame {
+        let delayed = self.buffer[self.head];
+        self.buffer[self.head] = (frame + delayed * self.params.feedback).flush_denormals();
+        self.head += 1;
+        if self.head == self.len {
+            self.head = 0;
+        }
+        frame + delayed * self.params.mix
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PARAMS: DelayParams = DelayParams {
+        beats: 1.,
+        bpm: 6000.,
+        feedback: 0.,
+        mix: 1.,
+    };
+
+    /// Index of the first non-zero output of `line` after an impulse.
+    fn echo_after(line: &mut DelayLine) -> usize {
+        line.process(Frame(1., 1.));
+        (1..)
+            .find(|_| line.process(Frame::default()).0 != 0.)
+            .unwrap()
+    }
+
+    #[test]
+    fn sample_rate_changes_keep_the_buffer() {
+        // A beat at 6000 BPM is 10 ms
+        let mut line = DelayLine::new(PARAMS);
+        let buffer = line.buffer.as_ptr();
+        for sample_rate in [48000, 44100, 96000, MAX_SAMPLE_RATE] {
+            line.set_sample_rate(sample_rate);
+            assert_eq!(echo_after(&mut line), sample_rate as usize / 100);
+        }
+        assert_eq!(line.buffer.as_ptr(), buffer);
+    }
+
+    #[test]
+    fn echoes_carry_over() {
+        let mut old = DelayLine::new(PARAMS);
+        old.set_sample_rate(48000);
+        old.process(Frame(1., 1.));
+        for _ in 0..99 {
+            old.process(Frame::default());
+        }
+        // Twice the delay, so the impulse is due in another 860 frames
+        let mut new = DelayLine::new(DelayParams {
+            beats: 2.,
+            ..PARAMS
+        });
+        new.set_sample_rate(48000);
+        new.take_echoes(&old);
+        let due = (0..)
+            .find(|_| new.process(Frame::default()).0 != 0.)
+            .unwrap();
+        assert_eq!(due, 960 - 100);
+    }
+}
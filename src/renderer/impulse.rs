@@ -0,0 +1,80 @@
+use super::mix_frames;
+use crate::{Frame, Renderer};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+/// Stored in [`Impulse`] until the impulse has been rendered
+const PENDING: u64 = u64::MAX;
+
+pub(crate) struct ImpulseRenderer {
+    target: u64,
+    emitted: Arc<AtomicU64>,
+    /// Where the next block starts when rendered outside of a mixer
+    next_frame: u64,
+}
+
+impl Renderer for ImpulseRenderer {
+    fn alive(&self) -> bool {
+        self.emitted.load(Ordering::SeqCst) == PENDING
+    }
+
+    fn name(&self) -> &'static str {
+        "Impulse"
+    }
+
+    fn render(&mut self, sample_rate: u32, channels: u16, data: &mut [f32]) {
+        self.render_at(self.next_frame, sample_rate, channels, data);
+    }
+
+    fn render_at(&mut self, start: u64, _sample_rate: u32, channels: u16, data: &mut [f32]) {
+        let frames = (data.len() / channels as usize) as u64;
+        self.next_frame = start + frames;
+        if self.target >= start + frames {
+            return;
+        }
+        // A target already in the past lands on the first frame of the block
+        let emitted = self.target.max(start);
+        let mut frame = start;
+        mix_frames(channels, data, || {
+            let value = if frame == emitted { 1. } else { 0. };
+            frame += 1;
+            (frame <= emitted + 1).then_some(Frame(value, value))
+        });
+        self.emitted.store(emitted, Ordering::SeqCst);
+    }
+}
+
+/// A single full-scale sample scheduled on the mixer's clock, for measuring
+/// latency and verifying scheduling.
+pub struct Impulse {
+    target: u64,
+    emitted: Arc<AtomicU64>,
+}
+impl Impulse {
+    pub(crate) fn new(target: u64) -> (Impulse, ImpulseRenderer) {
+        let emitted = Arc::new(AtomicU64::new(PENDING));
+        let renderer = ImpulseRenderer {
+            target,
+            emitted: Arc::clone(&emitted),
+            next_frame: 0,
+        };
+        (Self { target, emitted }, renderer)
+    }
+
+    /// The frame the impulse was requested at.
+    pub fn target(&self) -> u64 {
+        self.target
+    }
+
+    /// The frame of the mixer's clock the impulse was actually rendered at, or
+    /// `None` if it hasn't been yet.
+    ///
+    /// This equals [`Impulse::target`] unless the target had already passed
+    /// when the mixer picked the impulse up, in which case it is the first
+    /// frame of the block that did.
+    pub fn emitted_at(&self) -> Option<u64> {
+        Some(self.emitted.load(Ordering::SeqCst)).filter(|&it| it != PENDING)
+    }
+}
@@ -0,0 +1,114 @@
+use super::mix_frames;
+use crate::{Frame, Renderer, TempoMap, Waveform};
+use std::sync::{Arc, Weak};
+
+const CLICK_LENGTH: f64 = 0.03;
+
+#[derive(Debug, Clone)]
+pub struct MetronomeParams {
+    pub bpm: f64,
+    /// Every this many beats the click is accented. Zero disables accents.
+    pub beats_per_bar: u32,
+    /// Frame of the mixer's clock (see [`crate::AudioManager::frames_rendered`])
+    /// at which the first beat falls.
+    pub start_offset: u64,
+    pub amplifier: f32,
+    /// Follows tempo changes instead of `bpm`, with beat zero wherever the map
+    /// puts it. `start_offset` still mutes the clicks before it.
+    pub tempo_map: Option<TempoMap>,
+}
+impl Default for MetronomeParams {
+    fn default() -> Self {
+        Self {
+            bpm: 120.,
+            beats_per_bar: 4,
+            start_offset: 0,
+            amplifier: 1.,
+            tempo_map: None,
+        }
+    }
+}
+
+pub(crate) struct MetronomeRenderer {
+    params: MetronomeParams,
+    arc: Weak<()>,
+    /// Where the next block starts when rendered outside of a mixer
+    next_frame: u64,
+}
+
+impl MetronomeRenderer {
+    /// Click sound at global frame `frame`, if any.
+    #[inline]
+    fn click(&self, sample_rate: u32, frame: u64) -> f32 {
+        let Some(relative) = frame.checked_sub(self.params.start_offset) else {
+            return 0.;
+        };
+        let (beat, time) = match &self.params.tempo_map {
+            Some(map) => {
+                // The map may count frames at another rate than the renderer's
+                let scale = map.sample_rate() as f64 / sample_rate as f64;
+                let beat = map.sample_to_beat(frame as f64 * scale).floor();
+                let start = map.beat_to_sample(beat) / scale;
+                (beat, (frame as f64 - start) / sample_rate as f64)
+            }
+            None => {
+                let sample_rate = sample_rate as f64;
+                let beat_length = sample_rate * 60. / self.params.bpm;
+                // Beats are located from the absolute frame each time, so errors never accumulate
+                let beat = (relative as f64 / beat_length).floor();
+                (beat, (relative as f64 - beat * beat_length) / sample_rate)
+            }
+        };
+        if time >= CLICK_LENGTH {
+            return 0.;
+        }
+        let accent = self.params.beats_per_bar != 0
+            && (beat as i64).rem_euclid(self.params.beats_per_bar as i64) == 0;
+        let (frequency, amp) = if accent { (1500., 1.) } else { (1000., 0.6) };
+        let envelope = 1. - time / CLICK_LENGTH;
+        Waveform::Sine.sample((time * frequency).fract() as f32)
+            * (envelope * envelope) as f32
+            * amp
+            * self.params.amplifier
+    }
+}
+
+impl Renderer for MetronomeRenderer {
+    fn alive(&self) -> bool {
+        self.arc.strong_count() != 0
+    }
+
+    fn name(&self) -> &'static str {
+        "Metronome"
+    }
+
+    fn render(&mut self, sample_rate: u32, channels: u16, data: &mut [f32]) {
+        self.render_at(self.next_frame, sample_rate, channels, data);
+    }
+
+    fn render_at(&mut self, start_frame: u64, sample_rate: u32, channels: u16, data: &mut [f32]) {
+        let mut frame = start_frame;
+        mix_frames(channels, data, || {
+            let value = self.click(sample_rate, frame);
+            frame += 1;
+            Some(Frame(value, value))
+        });
+        self.next_frame = frame;
+    }
+}
+
+/// A click track locked to the mixer's clock. Dropping the handle stops it.
+pub struct Metronome {
+    _arc: Arc<()>,
+}
+impl Metronome {
+    pub(crate) fn new(params: MetronomeParams) -> (Metronome, MetronomeRenderer) {
+        let arc = Arc::new(());
+        let renderer = MetronomeRenderer {
+            params,
+            arc: Arc::downgrade(&arc),
+            next_frame: 0,
+        };
+        (Self { _arc: arc }, renderer)
+    }
+}
@@ -1,224 +1,778 @@
-use crate::{buffer_is_full, AudioClip, Frame, Renderer};
-use anyhow::{Context, Result};
+use super::{
+    delay::{DelayLine, DelayParams},
+    mix_frames,
+    pan::{apply_pan, validate_pan, PanLaw},
+    radio::{Radio, RadioParams},
+};
+use crate::{buffer_is_full, AudioClip, Frame, Renderer, Resampler, SasaError};
+use anyhow::{bail, Context, Result};
 use ringbuf::{HeapConsumer, HeapProducer, HeapRb};
 use std::sync::{
-    atomic::{AtomicBool, AtomicU32, Ordering},
+    atomic::{AtomicU32, AtomicU8, Ordering},
     Arc, Weak,
 };
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoopMode {
+    /// Mixes the tail of the clip with the given number of seconds from its
+    /// start while wrapping around.
+    Crossfade(f32),
+    /// Like [`LoopMode::Crossfade`], but fades the tail out and the start in
+    /// along an equal-power curve instead of summing them, so sustained sounds
+    /// don't get louder around the loop point.
+    EqualPower(f32),
+    /// Jumps straight back to the start, for audio authored to loop cleanly.
+    Seamless,
+}
+
+impl LoopMode {
+    /// Seconds from the start playing over the tail.
+    fn mix_time(self) -> f32 {
+        match self {
+            LoopMode::Crossfade(mix_time) | LoopMode::EqualPower(mix_time) => mix_time,
+            LoopMode::Seamless => 0.,
+        }
+    }
+
+    /// Fails on a crossfade time that is negative or not finite.
+    fn validate(mode: Option<Self>) -> Result<()> {
+        if let Some(mode) = mode {
+            let mix_time = mode.mix_time();
+            if !(mix_time.is_finite() && mix_time >= 0.) {
+                bail!(SasaError::InvalidParameter(format!(
+                    "invalid loop mix time {mix_time}"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Maps a seek target onto the point it would reach when playing from the
+    /// start. After the first pass, a crossfading loop wraps back to its mix
+    /// time, so the looped region is `[mix_time, length)`.
+    fn normalize(mode: Option<Self>, length: f32, position: f32) -> f32 {
+        let position = position.max(0.);
+        if length <= 0. {
+            return 0.;
+        }
+        if position < length {
+            return position;
+        }
+        let Some(mode) = mode else {
+            return length;
+        };
+        let mix_time = mode.mix_time();
+        let loop_length = length - mix_time;
+        if loop_length > 0. {
+            mix_time + (position - mix_time) % loop_length
+        } else {
+            0.
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct MusicParams {
-    pub loop_mix_time: f32,
+    pub loop_mode: Option<LoopMode>,
+    /// Relative to the clip's [`AudioClip::default_gain`].
     pub amplifier: f32,
     pub playback_rate: f32,
     pub command_buffer_size: usize,
+    pub resampler: Resampler,
+    /// From -1 (left) to 1 (right).
+    pub pan: f32,
+    pub pan_law: PanLaw,
+    /// Length in seconds of the fades [`Music::pause`] and [`Music::play`]
+    /// apply to avoid clicks. Zero makes them immediate.
+    pub pause_fade: f32,
 }
 impl Default for MusicParams {
     fn default() -> Self {
         Self {
-            loop_mix_time: -1.,
+            loop_mode: None,
             amplifier: 1.,
             playback_rate: 1.,
             command_buffer_size: 16,
+            resampler: Resampler::default(),
+            pan: 0.,
+            pan_law: PanLaw::default(),
+            pause_fade: 0.005,
+        }
+    }
+}
+
+impl MusicParams {
+    pub fn builder() -> MusicParamsBuilder {
+        MusicParamsBuilder::default()
+    }
+}
+
+/// Rejects rates that would freeze the play head (zero) or silently end the
+/// track (negative or NaN).
+fn validate_playback_rate(rate: f32) -> Result<()> {
+    if !(rate.is_finite() && rate > 0.) {
+        bail!(SasaError::InvalidParameter(format!(
+            "playback rate must be positive, got {rate}"
+        )));
+    }
+    Ok(())
+}
+
+/// Builds [`MusicParams`], checking the values once in
+/// [`MusicParamsBuilder::build`] instead of letting the renderer misbehave.
+#[derive(Debug, Clone, Default)]
+pub struct MusicParamsBuilder {
+    params: MusicParams,
+}
+
+impl MusicParamsBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn loop_mode(mut self, mode: Option<LoopMode>) -> Self {
+        self.params.loop_mode = mode;
+        self
+    }
+
+    pub fn amplifier(mut self, amp: f32) -> Self {
+        self.params.amplifier = amp;
+        self
+    }
+
+    pub fn playback_rate(mut self, rate: f32) -> Self {
+        self.params.playback_rate = rate;
+        self
+    }
+
+    pub fn command_buffer_size(mut self, size: usize) -> Self {
+        self.params.command_buffer_size = size;
+        self
+    }
+
+    pub fn resampler(mut self, resampler: Resampler) -> Self {
+        self.params.resampler = resampler;
+        self
+    }
+
+    pub fn pan(mut self, pan: f32) -> Self {
+        self.params.pan = pan;
+        self
+    }
+
+    pub fn pan_law(mut self, law: PanLaw) -> Self {
+        self.params.pan_law = law;
+        self
+    }
+
+    pub fn pause_fade(mut self, time: f32) -> Self {
+        self.params.pause_fade = time;
+        self
+    }
+
+    pub fn build(self) -> Result<MusicParams> {
+        let p = &self.params;
+        LoopMode::validate(p.loop_mode)?;
+        if !(p.amplifier.is_finite() && p.amplifier >= 0.) {
+            bail!(SasaError::InvalidParameter(format!(
+                "invalid amplifier {}",
+                p.amplifier
+            )));
+        }
+        validate_playback_rate(p.playback_rate)?;
+        if p.command_buffer_size == 0 {
+            bail!(SasaError::InvalidParameter(
+                "command buffer size must be positive".to_owned()
+            ));
+        }
+        validate_pan(p.pan)?;
+        if !(p.pause_fade.is_finite() && p.pause_fade >= 0.) {
+            bail!(SasaError::InvalidParameter(format!(
+                "invalid pause fade {}",
+                p.pause_fade
+            )));
+        }
+        Ok(self.params)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum MusicStatus {
+    Playing,
+    /// Paused explicitly, or not started yet.
+    Paused,
+    /// Reached the end of a clip that doesn't loop.
+    Ended,
+    /// Stopped by [`Music::fade_out`] or [`Music::fade_out_and_stop`].
+    FadedOut,
+}
+
+impl MusicStatus {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => MusicStatus::Playing,
+            1 => MusicStatus::Paused,
+            2 => MusicStatus::Ended,
+            _ => MusicStatus::FadedOut,
         }
     }
 }
 
 struct SharedState {
     position: AtomicU32, // float in bits
-    paused: AtomicBool,
+    status: AtomicU8,
 }
 impl Default for SharedState {
     fn default() -> Self {
         Self {
             position: AtomicU32::default(),
-            paused: AtomicBool::new(true),
+            status: AtomicU8::new(MusicStatus::Paused as u8),
         }
     }
 }
 
+/// Parameters for [`Music::set_state`], each left alone when `None`.
+#[derive(Debug, Clone, Default)]
+pub struct MusicUpdate {
+    pub amplifier: Option<f32>,
+    pub muted: Option<bool>,
+    pub low_pass: Option<f32>,
+    pub high_pass: Option<f32>,
+    pub pan: Option<f32>,
+    pub channel_gains: Option<(f32, f32)>,
+    pub playback_rate: Option<f32>,
+}
+
 enum MusicCommand {
     Pause,
+    PauseImmediate,
     Resume,
     SetAmplifier(f32),
+    SetMuted(bool),
     SeekTo(f32),
     SetLowPass(f32),
+    SetHighPass(f32),
+    SetDelay(Option<DelayLine>),
+    SetRadio(Option<RadioParams>),
+    SetLoopMode(Option<LoopMode>),
+    SetPan(f32),
+    SetPlaybackRate(f32),
     FadeIn(f32),
     FadeOut(f32),
+    FadeTo(f32, f32),
+    FadeOutAndStop(f32),
+    FadeOutToSilence(f32),
+    StopAt(Option<(f32, f32)>),
+    SetClip(AudioClip),
+    SetGainEnvelope(Vec<(f32, f32)>),
+    SetChannelGains(f32, f32),
+    SetState(MusicUpdate),
+}
+
+/// Whatever a command replaced on the audio thread, sent back to the handle to
+/// be freed there rather than in the callback.
+// Only ever dropped, never read
+#[allow(dead_code)]
+enum Retired {
+    Clip(AudioClip),
+    GainEnvelope(Vec<(f32, f32)>),
+    Delay(DelayLine),
+}
+
+#[derive(Clone, Copy)]
+enum FadeEnd {
+    /// Keep playing at the target gain
+    Continue,
+    Pause,
+    /// Remove the renderer from the mixer
+    Stop,
+    /// Keep playing silently until the next fade
+    Silence,
+    /// Pause without touching the amplifier, ending [`Music::pause`]
+    Suspend,
 }
+
 pub(crate) struct MusicRenderer {
     clip: AudioClip,
     settings: MusicParams,
     state: Weak<SharedState>,
     cons: HeapConsumer<MusicCommand>,
+    retired: HeapProducer<Retired>,
     paused: bool,
-    index: usize,
+    stopped: bool,
+    muted: bool,
+    /// Left at zero by [`Music::fade_out_to_silence`], without touching the
+    /// amplifier so that fading in restores it
+    silenced: bool,
+    /// Play head in frames of the clip, moved by a fractional step each output
+    /// frame so that rate changes never disturb its phase
+    head: f64,
     last_sample_rate: u32,
     low_pass: f32,
     last_output: Frame,
+    /// Cutoff of the high-pass in Hz, zero when disabled
+    high_pass: f32,
+    high_pass_coeff: f32,
+    // Last input and output of the high-pass, per channel
+    high_pass_input: Frame,
+    high_pass_output: Frame,
+    delay: Option<DelayLine>,
+    radio: Option<Radio>,
+    pan_gains: (f32, f32),
+    channel_gains: (f32, f32),
+    /// Product of `pan_gains` and `channel_gains`
+    output_gains: (f32, f32),
 
     fade_time: i32,
     fade_current: i32,
+    /// Gain applied on top of the amplifier once no fade is under way
+    fade_gain: f32,
+    fade_start: f32,
+    fade_target: f32,
+    fade_end: FadeEnd,
+    /// Position in seconds to end at, and how long before it to start fading.
+    /// The fade length is zeroed once the fade is under way.
+    stop_at: Option<(f32, f32)>,
+    /// `(position, gain)` points sorted by position, empty when unused
+    gain_envelope: Vec<(f32, f32)>,
 }
 impl MusicRenderer {
     fn prepare(&mut self, sample_rate: u32) {
         if self.last_sample_rate != sample_rate {
             let factor = sample_rate as f32 / self.last_sample_rate as f32;
-            self.index = (self.index as f32 * factor).round() as _;
             self.last_sample_rate = sample_rate;
             self.fade_time = (self.fade_time as f32 * factor).round() as _;
             self.fade_current = (self.fade_current as f32 * factor).round() as _;
+            self.update_high_pass();
+            if let Some(delay) = &mut self.delay {
+                delay.set_sample_rate(sample_rate);
+            }
+            if let Some(radio) = &mut self.radio {
+                radio.set_sample_rate(sample_rate);
+            }
         }
-        for cmd in self.cons.pop_iter() {
-            match cmd {
-                MusicCommand::Pause => {
-                    self.paused = true;
-                    if let Some(state) = self.state.upgrade() {
-                        state.paused.store(true, Ordering::SeqCst);
-                    }
+        while let Some(cmd) = self.cons.pop() {
+            self.apply(cmd, sample_rate);
+        }
+    }
+
+    fn apply(&mut self, cmd: MusicCommand, sample_rate: u32) {
+        match cmd {
+            MusicCommand::Pause => {
+                let time = self.settings.pause_fade;
+                // Nothing to click when silent already
+                if self.paused || self.silenced || time == 0. {
+                    self.set_status(MusicStatus::Paused);
+                } else {
+                    self.start_fade(self.fade_level(), 0., time, FadeEnd::Suspend);
                 }
-                MusicCommand::Resume => {
-                    self.paused = false;
-                    if let Some(state) = self.state.upgrade() {
-                        state.paused.store(false, Ordering::SeqCst);
+            }
+            MusicCommand::PauseImmediate => self.set_status(MusicStatus::Paused),
+            MusicCommand::Resume => {
+                if self.paused {
+                    self.reset_filters();
+                    let time = self.settings.pause_fade;
+                    if !self.silenced && time > 0. {
+                        self.start_fade(0., self.fade_gain, time, FadeEnd::Continue);
                     }
+                } else if self.fade_time != 0 && matches!(self.fade_end, FadeEnd::Suspend) {
+                    // Still fading out for a pause, which would pause again once
+                    // done. Turn around from where it got to instead.
+                    self.start_fade(
+                        self.fade_level(),
+                        self.fade_gain,
+                        self.settings.pause_fade,
+                        FadeEnd::Continue,
+                    );
                 }
-                MusicCommand::SetAmplifier(amp) => {
-                    self.settings.amplifier = amp;
-                }
-                MusicCommand::SeekTo(position) => {
-                    self.index = (position * sample_rate as f32 / self.settings.playback_rate)
-                        .round() as usize;
+                self.set_status(MusicStatus::Playing);
+            }
+            MusicCommand::SetAmplifier(amp) => {
+                self.settings.amplifier = amp;
+            }
+            MusicCommand::SetMuted(muted) => {
+                self.muted = muted;
+            }
+            MusicCommand::SeekTo(position) => {
+                let position =
+                    LoopMode::normalize(self.settings.loop_mode, self.clip.length(), position);
+                self.head = position as f64 * self.clip.sample_rate() as f64;
+                self.reset_filters();
+            }
+            MusicCommand::SetLowPass(low_pass) => {
+                self.low_pass = low_pass;
+            }
+            MusicCommand::SetHighPass(cutoff) => {
+                self.high_pass = cutoff;
+                self.update_high_pass();
+            }
+            MusicCommand::SetLoopMode(mode) => {
+                // The play head always stays within the clip, so once the
+                // loop is gone it just runs to the end
+                self.settings.loop_mode = mode;
+            }
+            MusicCommand::SetPan(pan) => {
+                self.settings.pan = pan;
+                self.pan_gains = self.settings.pan_law.gains(pan);
+                self.update_output_gains();
+            }
+            MusicCommand::SetChannelGains(left, right) => {
+                self.channel_gains = (left, right);
+                self.update_output_gains();
+            }
+            MusicCommand::SetPlaybackRate(rate) => {
+                self.settings.playback_rate = rate;
+            }
+            MusicCommand::SetDelay(mut delay) => {
+                if let Some(delay) = &mut delay {
+                    delay.set_sample_rate(sample_rate);
+                    if let Some(old) = &self.delay {
+                        delay.take_echoes(old);
+                    }
                 }
-                MusicCommand::SetLowPass(low_pass) => {
-                    self.low_pass = low_pass;
+                if let Some(old) = std::mem::replace(&mut self.delay, delay) {
+                    self.retire(Retired::Delay(old));
                 }
-                MusicCommand::FadeIn(time) => {
-                    if self.paused {
-                        self.paused = false;
-                        if let Some(state) = self.state.upgrade() {
-                            state.paused.store(false, Ordering::SeqCst);
-                        }
-                    }
-                    self.fade_time = (time * sample_rate as f32).round() as _;
-                    self.fade_current = 0;
+            }
+            MusicCommand::SetRadio(params) => match (params, &mut self.radio) {
+                (Some(params), Some(radio)) => radio.set_params(params, sample_rate),
+                (params, radio) => *radio = params.map(|it| Radio::new(it, sample_rate)),
+            },
+            MusicCommand::FadeIn(time) => {
+                if self.paused {
+                    self.set_status(MusicStatus::Playing);
                 }
-                MusicCommand::FadeOut(time) => {
-                    self.fade_time = (-time * sample_rate as f32).round() as _;
-                    self.fade_current = 0;
+                self.start_fade(0., 1., time, FadeEnd::Continue);
+            }
+            MusicCommand::FadeOut(time) => {
+                self.start_fade(self.fade_level(), 0., time, FadeEnd::Pause);
+            }
+            MusicCommand::FadeTo(target, time) => {
+                self.start_fade(self.fade_level(), target, time, FadeEnd::Continue);
+            }
+            MusicCommand::FadeOutAndStop(time) => {
+                self.start_fade(self.fade_level(), 0., time, FadeEnd::Stop);
+            }
+            MusicCommand::FadeOutToSilence(time) => {
+                self.start_fade(self.fade_level(), 0., time, FadeEnd::Silence);
+            }
+            MusicCommand::StopAt(stop_at) => {
+                self.stop_at = stop_at;
+            }
+            MusicCommand::SetGainEnvelope(points) => {
+                let old = std::mem::replace(&mut self.gain_envelope, points);
+                self.retire(Retired::GainEnvelope(old));
+            }
+            MusicCommand::SetClip(clip) => {
+                // Keep the position in seconds, wrapped or clamped to the
+                // new length. A non-looping track past its new end ends on
+                // the next frame.
+                let position =
+                    LoopMode::normalize(self.settings.loop_mode, clip.length(), self.position());
+                self.head = position as f64 * clip.sample_rate() as f64;
+                let old = std::mem::replace(&mut self.clip, clip);
+                self.retire(Retired::Clip(old));
+            }
+            MusicCommand::SetState(update) => {
+                let MusicUpdate {
+                    amplifier,
+                    muted,
+                    low_pass,
+                    high_pass,
+                    pan,
+                    channel_gains,
+                    playback_rate,
+                } = update;
+                let commands = [
+                    amplifier.map(MusicCommand::SetAmplifier),
+                    muted.map(MusicCommand::SetMuted),
+                    low_pass.map(MusicCommand::SetLowPass),
+                    high_pass.map(MusicCommand::SetHighPass),
+                    pan.map(MusicCommand::SetPan),
+                    channel_gains.map(|(left, right)| MusicCommand::SetChannelGains(left, right)),
+                    playback_rate.map(MusicCommand::SetPlaybackRate),
+                ];
+                for cmd in commands.into_iter().flatten() {
+                    self.apply(cmd, sample_rate);
                 }
             }
         }
     }
 
+    /// Hands `value` back to the handle. Should the handle not have drained
+    /// the ring in time, it's dropped right here instead.
+    fn retire(&mut self, value: Retired) {
+        let _ = self.retired.push(value);
+    }
+
+    /// Forgets filter state from before a jump in the position, which would
+    /// otherwise bleed into the new output as a transient. The filters start
+    /// over as on a fresh track.
+    fn reset_filters(&mut self) {
+        self.last_output = Frame::default();
+        self.high_pass_input = Frame::default();
+        self.high_pass_output = Frame::default();
+        if let Some(radio) = &mut self.radio {
+            radio.reset();
+        }
+    }
+
+    fn update_output_gains(&mut self) {
+        let (pan, channel) = (self.pan_gains, self.channel_gains);
+        self.output_gains = (pan.0 * channel.0, pan.1 * channel.1);
+    }
+
+    fn update_high_pass(&mut self) {
+        self.high_pass_coeff = if self.high_pass > 0. {
+            let rc = 1. / (std::f32::consts::TAU * self.high_pass);
+            rc / (rc + 1. / self.last_sample_rate as f32)
+        } else {
+            0.
+        };
+    }
+
+    fn set_status(&mut self, status: MusicStatus) {
+        self.paused = status != MusicStatus::Playing;
+        if let Some(state) = self.state.upgrade() {
+            state.status.store(status as u8, Ordering::SeqCst);
+        }
+    }
+
+    /// Ramps the fade gain from `start` to `target`, which multiplies the live
+    /// amplifier rather than replacing it.
+    fn start_fade(&mut self, start: f32, target: f32, time: f32, end: FadeEnd) {
+        // A fade cut short still settles the gain it was heading for, which is
+        // what playing again after a pause or fade out returns to
+        if self.fade_time != 0 && matches!(self.fade_end, FadeEnd::Continue) {
+            self.fade_gain = self.fade_target;
+        }
+        // Any fade takes over from silence, starting at zero
+        self.silenced = false;
+        self.fade_start = start;
+        self.fade_target = target;
+        self.fade_time = (time * self.last_sample_rate as f32).round() as _;
+        self.fade_current = 0;
+        self.fade_end = end;
+        if self.fade_time == 0 {
+            self.finish_fade();
+        }
+    }
+
+    /// Returns whether playback continues.
+    fn finish_fade(&mut self) -> bool {
+        self.fade_time = 0;
+        match self.fade_end {
+            FadeEnd::Continue => {
+                self.fade_gain = self.fade_target;
+                true
+            }
+            FadeEnd::Pause => {
+                self.set_status(MusicStatus::FadedOut);
+                false
+            }
+            FadeEnd::Stop => {
+                self.set_status(MusicStatus::FadedOut);
+                self.stopped = true;
+                false
+            }
+            FadeEnd::Silence => {
+                self.silenced = true;
+                true
+            }
+            FadeEnd::Suspend => {
+                self.set_status(MusicStatus::Paused);
+                false
+            }
+        }
+    }
+
     #[inline]
-    fn frame(&mut self, position: f32, delta: f32) -> Option<Frame> {
+    /// Reads the frame under the play head and moves it on by `delta` clip
+    /// frames. With `direct`, frames are read by index without interpolating,
+    /// which only holds when `delta` is exactly one and the head sits exactly
+    /// on a frame of the clip.
+    fn frame(&mut self, delta: f64, direct: bool) -> Option<Frame> {
+        let position = self.position();
+        if let Some((end, fade)) = self.stop_at {
+            if position >= end {
+                // Stops right at `end` even if the fade had no time to finish
+                self.set_status(MusicStatus::Ended);
+                self.stopped = true;
+                return None;
+            }
+            if fade > 0. && position >= end - fade {
+                let time = (end - position) / self.settings.playback_rate;
+                self.start_fade(self.fade_level(), 0., time, FadeEnd::Stop);
+                self.stop_at = Some((end, 0.));
+            }
+        }
         let s = &self.settings;
-        if let Some(mut frame) = self.clip.sample(position) {
-            if s.loop_mix_time >= 0. {
-                let pos = position + s.loop_mix_time - self.clip.length();
-                if pos >= 0. {
-                    if let Some(new_frame) = self.clip.sample(pos) {
-                        frame = frame + new_frame;
+        // A head left between frames by an earlier rate or a seek keeps being
+        // interpolated, truncating it would jump by up to a frame
+        let frame = if direct && self.head.fract() == 0. {
+            self.clip.frames().get(self.head as usize).copied()
+        } else {
+            s.resampler.sample(self.clip.frames(), self.head)
+        };
+        if let Some(mut frame) = frame {
+            match s.loop_mode {
+                Some(LoopMode::Crossfade(mix_time)) => {
+                    let pos = position + mix_time - self.clip.length();
+                    if pos >= 0. {
+                        if let Some(new_frame) = self.clip.sample_with(pos, s.resampler) {
+                            frame = frame + new_frame;
+                        }
                     }
                 }
-            }
-            self.index += 1;
-            let mut amp = s.amplifier;
-            if self.fade_time != 0 {
-                if self.fade_time > 0 {
-                    self.fade_current += 1;
-                    if self.fade_current >= self.fade_time {
-                        self.fade_time = 0;
-                    } else {
-                        amp *= self.fade_current as f32 / self.fade_time as f32;
-                    }
-                } else {
-                    self.fade_current -= 1;
-                    if self.fade_current <= self.fade_time {
-                        self.fade_time = 0;
-                        self.paused = true;
-                        if let Some(state) = self.state.upgrade() {
-                            state.paused.store(true, Ordering::SeqCst);
+                Some(LoopMode::EqualPower(mix_time)) if mix_time > 0. => {
+                    let pos = position + mix_time - self.clip.length();
+                    if pos >= 0. {
+                        if let Some(new_frame) = self.clip.sample_with(pos, s.resampler) {
+                            let angle = pos / mix_time * std::f32::consts::FRAC_PI_2;
+                            frame = frame * angle.cos() + new_frame * angle.sin();
                         }
-                        return None;
-                    } else {
-                        amp *= 1. - self.fade_current as f32 / self.fade_time as f32;
                     }
                 }
+                _ => {}
             }
-            Some(frame * amp)
-        } else if s.loop_mix_time >= 0. {
-            let position = position - self.clip.length() + s.loop_mix_time;
-            self.index = (position / delta).round() as _;
-            Some(if let Some(frame) = self.clip.sample(position) {
-                frame * s.amplifier
+            self.head += delta;
+            if self.fade_time != 0 {
+                self.fade_current += 1;
+                if self.fade_current >= self.fade_time && !self.finish_fade() {
+                    return None;
+                }
+            }
+            let amp = self.current_amplifier();
+            Some(frame * (amp * self.envelope_gain(position) * self.clip.default_gain()))
+        } else if let Some(mode) = s.loop_mode {
+            // Wrapping in clip frames keeps the fractional phase of the head
+            let clip_rate = self.clip.sample_rate() as f64;
+            self.head += mode.mix_time() as f64 * clip_rate - self.clip.frame_count() as f64;
+            let position = (self.head / clip_rate) as f32;
+            let frame = s.resampler.sample(self.clip.frames(), self.head);
+            self.head += delta;
+            Some(if let Some(frame) = frame {
+                frame
+                    * (self.current_amplifier()
+                        * self.envelope_gain(position)
+                        * self.clip.default_gain())
             } else {
                 Frame::default()
             })
         } else {
-            self.paused = true;
+            self.set_status(MusicStatus::Ended);
             None
         }
     }
 
+    /// Gain of the envelope at `position` seconds, interpolated linearly and
+    /// held at the edge points outside of them.
+    fn envelope_gain(&self, position: f32) -> f32 {
+        let points = &self.gain_envelope;
+        let (Some(first), Some(last)) = (points.first(), points.last()) else {
+            return 1.;
+        };
+        let next = points.partition_point(|&(time, _)| time <= position);
+        if next == 0 {
+            return first.1;
+        }
+        if next == points.len() {
+            return last.1;
+        }
+        let (t0, g0) = points[next - 1];
+        let (t1, g1) = points[next];
+        g0 + (g1 - g0) * (position - t0) / (t1 - t0)
+    }
+
+    /// Gain of the fade stage, following the ramp while a fade is under way.
+    fn fade_level(&self) -> f32 {
+        if self.fade_time == 0 {
+            self.fade_gain
+        } else {
+            self.fade_start
+                + (self.fade_target - self.fade_start) * self.fade_current as f32
+                    / self.fade_time as f32
+        }
+    }
+
+    fn current_amplifier(&self) -> f32 {
+        if self.silenced {
+            0.
+        } else {
+            self.settings.amplifier * self.fade_level()
+        }
+    }
+
+    /// Position of the play head in seconds.
     #[inline]
-    fn position(&self, delta: f32) -> f32 {
-        self.index as f32 * delta
+    fn position(&self) -> f32 {
+        (self.head / self.clip.sample_rate() as f64) as f32
     }
 
     #[inline(always)]
     fn update_and_get(&mut self, frame: Frame) -> Frame {
-        self.last_output = self.last_output * self.low_pass + frame * (1. - self.low_pass);
-        self.last_output
+        self.last_output =
+            (self.last_output * self.low_pass + frame * (1. - self.low_pass)).flush_denormals();
+        let mut output = self.last_output;
+        if self.high_pass > 0. {
+            // One-pole high-pass on top of the low-pass, together forming a band-pass
+            self.high_pass_output = ((self.high_pass_output + output - self.high_pass_input)
+                * self.high_pass_coeff)
+                .flush_denormals();
+            self.high_pass_input = output;
+            output = self.high_pass_output;
+        }
+        if let Some(delay) = &mut self.delay {
+            output = delay.process(output);
+        }
+        if let Some(radio) = &mut self.radio {
+            output = radio.process(output);
+        }
+        apply_pan(output, self.output_gains)
     }
 }
 
 impl Renderer for MusicRenderer {
     fn alive(&self) -> bool {
-        self.state.strong_count() != 0
+        !self.stopped && self.state.strong_count() != 0
     }
 
-    fn render_mono(&mut self, sample_rate: u32, data: &mut [f32]) {
-        self.prepare(sample_rate);
-        if !self.paused {
-            let delta = 1. / sample_rate as f64 * self.settings.playback_rate as f64;
-            let mut position = self.index as f64 * delta;
-            for sample in data.iter_mut() {
-                if let Some(frame) = self.frame(position as f32, delta as f32) {
-                    *sample += self.update_and_get(frame).avg();
-                } else {
-                    break;
-                }
-                position += delta;
-            }
-            if let Some(state) = self.state.upgrade() {
-                state
-                    .position
-                    .store(self.position(delta as f32).to_bits(), Ordering::SeqCst);
-            }
-        }
+    fn name(&self) -> &'static str {
+        "Music"
+    }
+
+    fn position(&self) -> Option<f32> {
+        let state = self.state.upgrade()?;
+        Some(f32::from_bits(state.position.load(Ordering::SeqCst)))
     }
 
-    fn render_stereo(&mut self, sample_rate: u32, data: &mut [f32]) {
+    fn render(&mut self, sample_rate: u32, channels: u16, data: &mut [f32]) {
         self.prepare(sample_rate);
         if !self.paused {
-            let delta = 1. / sample_rate as f64 * self.settings.playback_rate as f64;
-            let mut position = self.index as f64 * delta;
-            for sample in data.chunks_exact_mut(2) {
-                if let Some(frame) = self.frame(position as f32, delta as f32) {
-                    let frame = self.update_and_get(frame);
-                    sample[0] += frame.0;
-                    sample[1] += frame.1;
-                } else {
-                    break;
+            let delta = self.clip.sample_rate() as f64 / sample_rate as f64
+                * self.settings.playback_rate as f64;
+            // The common case of playing at the clip's own rate needs no interpolation
+            let direct = delta == 1.;
+            let muted = self.muted;
+            let mut next = || {
+                let frame = self.frame(delta, direct)?;
+                Some(self.update_and_get(frame))
+            };
+            if muted {
+                // Keep playing, just without output
+                for _ in 0..data.len() / channels as usize {
+                    if next().is_none() {
+                        break;
+                    }
                 }
-                position += delta;
+            } else {
+                mix_frames(channels, data, next);
             }
             if let Some(state) = self.state.upgrade() {
                 state
                     .position
-                    .store(self.position(delta as f32).to_bits(), Ordering::SeqCst);
+                    .store(self.position().to_bits(), Ordering::SeqCst);
             }
         }
     }
@@ -227,26 +781,73 @@ impl Renderer for MusicRenderer {
 pub struct Music {
     arc: Arc<SharedState>,
     prod: HeapProducer<MusicCommand>,
+    retired: HeapConsumer<Retired>,
+    length: f32,
+    loop_mode: Option<LoopMode>,
 }
 impl Music {
     pub(crate) fn new(clip: AudioClip, settings: MusicParams) -> (Music, MusicRenderer) {
         let (prod, cons) = HeapRb::new(settings.command_buffer_size).split();
+        // Drained before every command that can retire something, so this
+        // holds at most one entry per command in flight plus the new one
+        let (retired_prod, retired) = HeapRb::new(settings.command_buffer_size + 1).split();
         let arc = Arc::default();
+        let length = clip.length();
+        let loop_mode = settings.loop_mode;
+        let pan_gains = settings.pan_law.gains(settings.pan);
         let renderer = MusicRenderer {
             clip,
             settings,
             state: Arc::downgrade(&arc),
             cons,
+            retired: retired_prod,
             paused: true,
-            index: 0,
+            stopped: false,
+            muted: false,
+            silenced: false,
+            head: 0.,
             last_sample_rate: 1,
             low_pass: 0.,
             last_output: Frame(0., 0.),
+            high_pass: 0.,
+            high_pass_coeff: 0.,
+            high_pass_input: Frame(0., 0.),
+            high_pass_output: Frame(0., 0.),
+            delay: None,
+            radio: None,
+            pan_gains,
+            channel_gains: (1., 1.),
+            output_gains: pan_gains,
 
             fade_time: 0,
             fade_current: 0,
+            fade_gain: 1.,
+            fade_start: 0.,
+            fade_target: 0.,
+            fade_end: FadeEnd::Continue,
+            stop_at: None,
+            gain_envelope: Vec::new(),
         };
-        (Self { arc, prod }, renderer)
+        (
+            Self {
+                arc,
+                prod,
+                retired,
+                length,
+                loop_mode,
+            },
+            renderer,
+        )
+    }
+
+    /// Creates a handle with its renderer, detached from any mixer, for
+    /// stepping it deterministically with [`crate::render_frames`] in tests or
+    /// offline tools.
+    ///
+    /// Commands sent through the handle take effect at the start of the next
+    /// render, as they would with a mixer.
+    pub fn new_offline(clip: AudioClip, settings: MusicParams) -> (Music, impl Renderer) {
+        Self::new(clip, settings)
     }
 
     pub fn play(&mut self) -> Result<()> {
@@ -256,6 +857,9 @@ impl Music {
             .context("play music")
     }
 
+    /// Pauses after a fade of [`MusicParams::pause_fade`], so that the
+    /// waveform isn't cut off with a click. [`Music::play`] fades back in the
+    /// same way.
     pub fn pause(&mut self) -> Result<()> {
         self.prod
             .push(MusicCommand::Pause)
@@ -263,8 +867,22 @@ impl Music {
             .context("pause")
     }
 
+    /// Pauses right away without a fade, e.g. to freeze on a frame exactly.
+    pub fn pause_immediate(&mut self) -> Result<()> {
+        self.prod
+            .push(MusicCommand::PauseImmediate)
+            .map_err(buffer_is_full)
+            .context("pause immediate")
+    }
+
+    /// Whether the music isn't playing, for whatever reason. See
+    /// [`Music::status`] for the reason.
     pub fn paused(&mut self) -> bool {
-        self.arc.paused.load(Ordering::SeqCst)
+        self.status() != MusicStatus::Playing
+    }
+
+    pub fn status(&self) -> MusicStatus {
+        MusicStatus::from_u8(self.arc.status.load(Ordering::SeqCst))
     }
 
     pub fn set_amplifier(&mut self, amp: f32) -> Result<()> {
@@ -274,11 +892,86 @@ impl Music {
             .context("set amplifier")
     }
 
+    /// Enables, changes or disables looping on the fly. Disabling it mid-loop
+    /// lets the current pass play to the end of the clip.
+    pub fn set_loop_mode(&mut self, mode: Option<LoopMode>) -> Result<()> {
+        LoopMode::validate(mode)?;
+        self.prod
+            .push(MusicCommand::SetLoopMode(mode))
+            .map_err(buffer_is_full)
+            .context("set loop mode")?;
+        self.loop_mode = mode;
+        Ok(())
+    }
+
+    /// Pans the music from -1 (left) to 1 (right), following the
+    /// [`MusicParams::pan_law`] it was created with.
+    pub fn set_pan(&mut self, pan: f32) -> Result<()> {
+        validate_pan(pan)?;
+        self.prod
+            .push(MusicCommand::SetPan(pan))
+            .map_err(buffer_is_full)
+            .context("set pan")
+    }
+
+    /// Scales the left and right channel independently, e.g. to correct an
+    /// asset that is louder on one side. Unlike [`Music::set_pan`] this is a
+    /// plain multiplication without any power preservation, and it applies on
+    /// top of the pan.
+    pub fn set_channel_gains(&mut self, left: f32, right: f32) -> Result<()> {
+        self.prod
+            .push(MusicCommand::SetChannelGains(left, right))
+            .map_err(buffer_is_full)
+            .context("set channel gains")
+    }
+
+    /// Applies all the given parameters in the same block, e.g. to duck and
+    /// filter at once, using a single slot of the command buffer.
+    pub fn set_state(&mut self, update: MusicUpdate) -> Result<()> {
+        if let Some(pan) = update.pan {
+            validate_pan(pan)?;
+        }
+        if let Some(rate) = update.playback_rate {
+            validate_playback_rate(rate)?;
+        }
+        self.prod
+            .push(MusicCommand::SetState(update))
+            .map_err(buffer_is_full)
+            .context("set state")
+    }
+
+    /// Changes the playback speed, keeping the current position.
+    pub fn set_playback_rate(&mut self, rate: f32) -> Result<()> {
+        validate_playback_rate(rate)?;
+        self.prod
+            .push(MusicCommand::SetPlaybackRate(rate))
+            .map_err(buffer_is_full)
+            .context("set playback rate")
+    }
+
+    /// Silences the output while playback (and thus `position`) keeps advancing.
+    pub fn set_muted(&mut self, muted: bool) -> Result<()> {
+        self.prod
+            .push(MusicCommand::SetMuted(muted))
+            .map_err(buffer_is_full)
+            .context("set muted")
+    }
+
+    /// Seeks to `position` seconds. [`Music::position`] reflects the new
+    /// position right away, before the renderer catches up.
+    ///
+    /// On a looping track, positions past the end are wrapped into the loop as
+    /// if playback had got there on its own.
     pub fn seek_to(&mut self, position: f32) -> Result<()> {
         self.prod
             .push(MusicCommand::SeekTo(position))
             .map_err(buffer_is_full)
-            .context("seek to")
+            .context("seek to")?;
+        let position = LoopMode::normalize(self.loop_mode, self.length, position);
+        self.arc
+            .position
+            .store(position.to_bits(), Ordering::SeqCst);
+        Ok(())
     }
 
     pub fn set_low_pass(&mut self, low_pass: f32) -> Result<()> {
@@ -288,6 +981,50 @@ impl Music {
             .context("set low pass")
     }
 
+    /// Filters out frequencies below `cutoff` Hz, zero disabling the filter.
+    /// Combined with [`Music::set_low_pass`] this makes a band-pass, e.g. for a
+    /// telephone effect.
+    pub fn set_high_pass(&mut self, cutoff: f32) -> Result<()> {
+        self.prod
+            .push(MusicCommand::SetHighPass(cutoff))
+            .map_err(buffer_is_full)
+            .context("set high pass")
+    }
+
+    /// Adds an echo synced to a tempo, or removes it with `None`. Changing the
+    /// tempo of an existing delay keeps its pending echoes.
+    ///
+    /// The line is allocated here, long enough for output rates up to 192 kHz,
+    /// and the one it replaces is freed here as well, as with
+    /// [`Music::set_clip`].
+    pub fn set_delay(&mut self, params: Option<DelayParams>) -> Result<()> {
+        if let Some(p) = &params {
+            if !(p.beats.is_finite() && p.beats > 0. && p.bpm.is_finite() && p.bpm > 0.) {
+                bail!(SasaError::InvalidParameter(format!(
+                    "invalid delay time of {} beats at {} BPM",
+                    p.beats, p.bpm
+                )));
+            }
+        }
+        self.free_retired();
+        self.prod
+            .push(MusicCommand::SetDelay(params.map(DelayLine::new)))
+            .map_err(buffer_is_full)
+            .context("set delay")
+    }
+
+    /// Makes the music sound like it's coming out of an old radio: band-passed,
+    /// lightly saturated and optionally bitcrushed. `None` removes the effect.
+    ///
+    /// This runs after the other filters and the delay, so it can be combined
+    /// with them for custom flavors.
+    pub fn set_radio_effect(&mut self, params: Option<RadioParams>) -> Result<()> {
+        self.prod
+            .push(MusicCommand::SetRadio(params))
+            .map_err(buffer_is_full)
+            .context("set radio effect")
+    }
+
     pub fn fade_in(&mut self, time: f32) -> Result<()> {
         self.prod
             .push(MusicCommand::FadeIn(time))
@@ -295,6 +1032,8 @@ impl Music {
             .context("fade in")
     }
 
+    /// Fades to zero over `time` seconds, then stops output and playback with
+    /// [`MusicStatus::FadedOut`]. [`Music::play`] continues from there.
     pub fn fade_out(&mut self, time: f32) -> Result<()> {
         self.prod
             .push(MusicCommand::FadeOut(time))
@@ -302,7 +1041,320 @@ impl Music {
             .context("fade out")
     }
 
+    /// Ramps a gain applied on top of the amplifier from its current value to
+    /// `target` over `time` seconds, so [`Music::set_amplifier`] keeps working
+    /// independently. Unlike [`Music::fade_out`], playback keeps running
+    /// afterwards.
+    pub fn fade_to(&mut self, target: f32, time: f32) -> Result<()> {
+        if !(target.is_finite() && target >= 0.) {
+            bail!(SasaError::InvalidParameter(format!(
+                "invalid fade target {target}"
+            )));
+        }
+        if !(time.is_finite() && time >= 0.) {
+            bail!(SasaError::InvalidParameter(format!(
+                "invalid fade time {time}"
+            )));
+        }
+        self.prod
+            .push(MusicCommand::FadeTo(target, time))
+            .map_err(buffer_is_full)
+            .context("fade to")
+    }
+
+    /// Fades out like [`Music::fade_out`], then removes the music from the
+    /// mixer. The handle can't be played again afterwards.
+    pub fn fade_out_and_stop(&mut self, time: f32) -> Result<()> {
+        self.prod
+            .push(MusicCommand::FadeOutAndStop(time))
+            .map_err(buffer_is_full)
+            .context("fade out and stop")
+    }
+
+    /// Ends playback once the position reaches `end` seconds, fading out over
+    /// the last `fade` seconds before it, after which the music is removed
+    /// from the mixer. `None` cancels a pending stop.
+    pub fn set_stop_at(&mut self, end: Option<f32>, fade: f32) -> Result<()> {
+        self.prod
+            .push(MusicCommand::StopAt(end.map(|end| (end, fade))))
+            .map_err(buffer_is_full)
+            .context("set stop at")
+    }
+
+    /// Replaces the clip being played, for hot reloading or switching an intro
+    /// over to its loop. The position carries over, wrapped into the loop or
+    /// clamped to the end if the new clip is shorter.
+    ///
+    /// The old clip is handed back and freed on the next call to this,
+    /// [`Music::set_gain_envelope`] or [`Music::set_delay`], or when the handle
+    /// is dropped, so that the audio thread never deallocates it.
+    pub fn set_clip(&mut self, clip: AudioClip) -> Result<()> {
+        self.free_retired();
+        let length = clip.length();
+        self.prod
+            .push(MusicCommand::SetClip(clip))
+            .map_err(buffer_is_full)
+            .context("set clip")?;
+        self.length = length;
+        Ok(())
+    }
+
+    /// Fades to zero over `time` seconds but, unlike [`Music::fade_out`],
+    /// keeps playing: the position advances over silence, [`Music::status`]
+    /// stays [`MusicStatus::Playing`] and [`Music::fade_in`] brings it back
+    /// to its amplifier without seeking.
+    pub fn fade_out_to_silence(&mut self, time: f32) -> Result<()> {
+        self.prod
+            .push(MusicCommand::FadeOutToSilence(time))
+            .map_err(buffer_is_full)
+            .context("fade out to silence")
+    }
+
+    /// Automates the volume with `(position, gain)` points, given in seconds of
+    /// the clip. The gain is interpolated linearly between them, held at the
+    /// first and last point outside of them and multiplied with the amplifier
+    /// and any fades. An empty list removes the envelope.
+    ///
+    /// The replaced points are freed on this side, as with [`Music::set_clip`].
+    pub fn set_gain_envelope(&mut self, mut points: Vec<(f32, f32)>) -> Result<()> {
+        self.free_retired();
+        if let Some(&(time, gain)) = points
+            .iter()
+            .find(|(time, gain)| !(time.is_finite() && gain.is_finite()))
+        {
+            bail!(SasaError::InvalidParameter(format!(
+                "invalid envelope point ({time}, {gain})"
+            )));
+        }
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        self.prod
+            .push(MusicCommand::SetGainEnvelope(points))
+            .map_err(buffer_is_full)
+            .context("set gain envelope")
+    }
+
     pub fn position(&self) -> f32 {
         f32::from_bits(self.arc.position.load(Ordering::SeqCst))
     }
+
+    /// Length of the current clip in seconds.
+    pub fn duration(&self) -> f32 {
+        self.length
+    }
+
+    /// Seeks to `fraction` of [`Music::duration`], clamped to `[0, 1]`, as a
+    /// progress bar would. Does nothing on an empty clip or a NaN fraction.
+    pub fn seek_fraction(&mut self, fraction: f32) -> Result<()> {
+        if self.length <= 0. || fraction.is_nan() {
+            return Ok(());
+        }
+        self.seek_to(fraction.clamp(0., 1.) * self.length)
+    }
+
+    fn free_retired(&mut self) {
+        self.retired.pop_iter().for_each(drop);
+    }
+}
+impl Drop for Music {
+    fn drop(&mut self) {
+        self.free_retired();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render_frames;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    fn constant_clip(frames: usize) -> AudioClip {
+        AudioClip::from_raw(vec![Frame(0.5, 0.5); frames], SAMPLE_RATE)
+    }
+
+    #[test]
+    fn play_during_pause_fade_resumes() {
+        let (mut music, mut renderer) =
+            Music::new_offline(constant_clip(SAMPLE_RATE as usize), MusicParams::default());
+        music.play().unwrap();
+        render_frames(&mut renderer, SAMPLE_RATE, 100);
+        music.pause().unwrap();
+        // Less than the 5 ms pause fade
+        render_frames(&mut renderer, SAMPLE_RATE, 100);
+        music.play().unwrap();
+        let out = render_frames(&mut renderer, SAMPLE_RATE, 1000);
+        assert_eq!(music.status(), MusicStatus::Playing);
+        assert!(out[out.len() - 2..].iter().all(|&it| it > 0.4));
+    }
+
+    #[test]
+    fn amplifier_set_right_after_play_survives_the_fade() {
+        let (mut music, mut renderer) =
+            Music::new_offline(constant_clip(SAMPLE_RATE as usize), MusicParams::default());
+        music.play().unwrap();
+        music.set_amplifier(0.5).unwrap();
+        // Well past the 5 ms fade of play
+        let out = render_frames(&mut renderer, SAMPLE_RATE, 1000);
+        let (left, _) = PanLaw::default().gains(0.);
+        assert!((out[out.len() - 2] - 0.25 * left).abs() < 1e-4);
+    }
+
+    #[test]
+    fn amplifier_set_during_fade_in_holds_after_it() {
+        let (mut music, mut renderer) =
+            Music::new_offline(constant_clip(SAMPLE_RATE as usize), MusicParams::default());
+        music.fade_in(0.01).unwrap();
+        render_frames(&mut renderer, SAMPLE_RATE, 100);
+        music.set_amplifier(0.5).unwrap();
+        let out = render_frames(&mut renderer, SAMPLE_RATE, 1000);
+        let (left, _) = PanLaw::default().gains(0.);
+        assert!((out[out.len() - 2] - 0.25 * left).abs() < 1e-4);
+    }
+
+    #[test]
+    fn fade_to_rejects_invalid_values() {
+        let (mut music, _renderer) = Music::new_offline(constant_clip(100), MusicParams::default());
+        for (target, time) in [(0.5, -1.), (0.5, f32::NAN), (-0.5, 1.)] {
+            let err = music.fade_to(target, time).unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<SasaError>(),
+                Some(SasaError::InvalidParameter(_))
+            ));
+        }
+        assert!(music.fade_to(0.5, 0.).is_ok());
+    }
+
+    fn is_invalid<T>(result: Result<T>) -> bool {
+        matches!(
+            result.err().unwrap().downcast_ref(),
+            Some(SasaError::InvalidParameter(_))
+        )
+    }
+
+    #[test]
+    fn params_builder_rejects_invalid_values() {
+        let params = MusicParams::builder()
+            .amplifier(0.5)
+            .pan(-1.)
+            .loop_mode(Some(LoopMode::Crossfade(0.)))
+            .build()
+            .unwrap();
+        assert_eq!(params.amplifier, 0.5);
+        assert_eq!(params.pan, -1.);
+
+        let builder = MusicParams::builder;
+        assert!(is_invalid(builder().amplifier(-1.).build()));
+        assert!(is_invalid(builder().amplifier(f32::NAN).build()));
+        assert!(is_invalid(builder().playback_rate(0.).build()));
+        assert!(is_invalid(builder().command_buffer_size(0).build()));
+        assert!(is_invalid(builder().pan(1.5).build()));
+        assert!(is_invalid(builder().pan(f32::NAN).build()));
+        assert!(is_invalid(builder().pause_fade(-0.1).build()));
+        for mode in [LoopMode::Crossfade(-1.), LoopMode::EqualPower(f32::NAN)] {
+            assert!(is_invalid(builder().loop_mode(Some(mode)).build()));
+        }
+    }
+
+    #[test]
+    fn set_loop_mode_rejects_invalid_mix_times() {
+        let (mut music, _renderer) = Music::new_offline(constant_clip(100), MusicParams::default());
+        for mode in [LoopMode::Crossfade(-1.), LoopMode::EqualPower(f32::NAN)] {
+            assert!(is_invalid(music.set_loop_mode(Some(mode))));
+        }
+        assert!(music.set_loop_mode(Some(LoopMode::Crossfade(0.5))).is_ok());
+        assert!(music.set_loop_mode(None).is_ok());
+    }
+
+    #[test]
+    fn live_pan_rejects_invalid_values() {
+        let (mut music, _renderer) = Music::new_offline(constant_clip(100), MusicParams::default());
+        assert!(is_invalid(music.set_pan(f32::NAN)));
+        assert!(is_invalid(music.set_pan(-1.5)));
+        assert!(is_invalid(music.set_state(MusicUpdate {
+            pan: Some(f32::INFINITY),
+            ..Default::default()
+        })));
+        assert!(music.set_pan(1.).is_ok());
+    }
+
+    #[test]
+    fn live_playback_rate_rejects_invalid_values() {
+        let (mut music, _renderer) = Music::new_offline(constant_clip(100), MusicParams::default());
+        for rate in [0., -1., f32::NAN, f32::INFINITY] {
+            assert!(is_invalid(music.set_playback_rate(rate)));
+            assert!(is_invalid(music.set_state(MusicUpdate {
+                playback_rate: Some(rate),
+                ..Default::default()
+            })));
+        }
+        assert!(music.set_playback_rate(0.5).is_ok());
+    }
+
+    #[test]
+    fn replaced_clip_is_freed_by_the_handle() {
+        let (mut music, mut renderer) =
+            Music::new_offline(constant_clip(100), MusicParams::default());
+        music.set_clip(constant_clip(200)).unwrap();
+        render_frames(&mut renderer, SAMPLE_RATE, 10);
+        // The first clip is back on this side, waiting to be freed
+        assert_eq!(music.retired.len(), 1);
+        music.set_clip(constant_clip(300)).unwrap();
+        assert!(music.retired.is_empty());
+    }
+
+    #[test]
+    fn replaced_gain_envelope_is_freed_by_the_handle() {
+        let (mut music, mut renderer) =
+            Music::new_offline(constant_clip(100), MusicParams::default());
+        music.set_gain_envelope(vec![(0., 1.)]).unwrap();
+        render_frames(&mut renderer, SAMPLE_RATE, 10);
+        assert_eq!(music.retired.len(), 1);
+        music.set_gain_envelope(Vec::new()).unwrap();
+        assert!(music.retired.is_empty());
+    }
+
+    #[test]
+    fn replaced_delay_is_freed_by_the_handle() {
+        let (mut music, mut renderer) =
+            Music::new_offline(constant_clip(100), MusicParams::default());
+        music.set_delay(Some(DelayParams::default())).unwrap();
+        render_frames(&mut renderer, SAMPLE_RATE, 10);
+        assert!(music.retired.is_empty());
+        music.set_delay(Some(DelayParams::default())).unwrap();
+        render_frames(&mut renderer, SAMPLE_RATE, 10);
+        assert_eq!(music.retired.len(), 1);
+        music.set_delay(None).unwrap();
+        assert!(music.retired.is_empty());
+        assert!(music
+            .set_delay(Some(DelayParams {
+                bpm: 0.,
+                ..DelayParams::default()
+            }))
+            .is_err());
+    }
+
+    #[test]
+    fn fractional_head_is_interpolated_at_unit_rate() {
+        let ramp = (0..100).map(|i| Frame(i as f32, i as f32)).collect();
+        let clip = AudioClip::from_raw(ramp, SAMPLE_RATE);
+        let params = MusicParams {
+            pause_fade: 0.,
+            ..MusicParams::default()
+        };
+        let (mut music, mut renderer) = Music::new_offline(clip.clone(), params);
+        let start = 10.5 / SAMPLE_RATE as f32;
+        music.seek_to(start).unwrap();
+        music.play().unwrap();
+        let out = render_frames(&mut renderer, SAMPLE_RATE, 20);
+        let (left, _) = PanLaw::default().gains(0.);
+        for (i, frame) in out.chunks_exact(2).enumerate() {
+            let position = start + i as f32 / SAMPLE_RATE as f32;
+            let expected = clip.sample_with(position, Resampler::Linear).unwrap().0 * left;
+            assert!(
+                (frame[0] - expected).abs() < 1e-3,
+                "{} != {expected}",
+                frame[0]
+            );
+        }
+    }
 }
@@ -0,0 +1,120 @@
+use super::mix_frames;
+use crate::{Frame, Renderer};
+
+/// A small xorshift64* generator, so that noise is reproducible from a seed.
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // SplitMix64 scrambles the seed so that small seeds (and zero) work
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        Self((z ^ (z >> 31)).max(1))
+    }
+
+    #[inline]
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Uniform in `[-1, 1)`.
+    #[inline]
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 23) as f32 - 1.
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NoiseColor {
+    #[default]
+    White,
+    /// Noise falling off at -3dB per octave.
+    Pink,
+}
+
+#[derive(Debug, Clone)]
+pub struct NoiseParams {
+    pub color: NoiseColor,
+    pub amplifier: f32,
+    pub seed: u64,
+}
+impl Default for NoiseParams {
+    fn default() -> Self {
+        Self {
+            color: NoiseColor::default(),
+            amplifier: 1.,
+            seed: 0,
+        }
+    }
+}
+
+/// A renderer generating white or pink noise.
+pub struct Noise {
+    params: NoiseParams,
+    rng: Rng,
+    // State of Paul Kellet's pinking filter
+    pink: [f32; 7],
+    remaining: Option<f64>,
+}
+
+impl Noise {
+    /// With a `duration` (in seconds) the renderer dies once that much has
+    /// been rendered, otherwise it plays forever.
+    pub fn new(duration: Option<f32>, params: NoiseParams) -> Self {
+        Self {
+            rng: Rng::new(params.seed),
+            params,
+            pink: [0.; 7],
+            remaining: duration.map(f64::from),
+        }
+    }
+
+    #[inline]
+    fn next(&mut self, delta: f64) -> Option<f32> {
+        if let Some(remaining) = &mut self.remaining {
+            if *remaining <= 0. {
+                return None;
+            }
+            *remaining -= delta;
+        }
+        let white = self.rng.next_f32();
+        let value = match self.params.color {
+            NoiseColor::White => white,
+            NoiseColor::Pink => {
+                let b = &mut self.pink;
+                b[0] = 0.99886 * b[0] + white * 0.0555179;
+                b[1] = 0.99332 * b[1] + white * 0.0750759;
+                b[2] = 0.96900 * b[2] + white * 0.153852;
+                b[3] = 0.86650 * b[3] + white * 0.3104856;
+                b[4] = 0.55000 * b[4] + white * 0.5329522;
+                b[5] = -0.7616 * b[5] - white * 0.0168980;
+                let pink = b.iter().sum::<f32>() + white * 0.5362;
+                b[6] = white * 0.115926;
+                // Brings the peak level roughly in line with white noise
+                pink * 0.11
+            }
+        };
+        Some(value * self.params.amplifier)
+    }
+}
+
+impl Renderer for Noise {
+    fn alive(&self) -> bool {
+        !matches!(self.remaining, Some(remaining) if remaining <= 0.)
+    }
+
+    fn name(&self) -> &'static str {
+        "Noise"
+    }
+
+    fn render(&mut self, sample_rate: u32, channels: u16, data: &mut [f32]) {
+        let delta = 1. / sample_rate as f64;
+        mix_frames(channels, data, || {
+            self.next(delta).map(|value| Frame(value, value))
+        });
+    }
+}
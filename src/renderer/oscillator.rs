@@ -0,0 +1,95 @@
+use super::mix_frames;
+use crate::{Frame, Renderer};
+use std::f32::consts::TAU;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Waveform {
+    #[default]
+    Sine,
+    Square,
+    Sawtooth,
+    Triangle,
+}
+
+impl Waveform {
+    /// Value of the waveform at `phase`, measured in cycles within `[0, 1)`.
+    pub fn sample(self, phase: f32) -> f32 {
+        match self {
+            Waveform::Sine => (phase * TAU).sin(),
+            Waveform::Square => {
+                if phase < 0.5 {
+                    1.
+                } else {
+                    -1.
+                }
+            }
+            Waveform::Sawtooth => 2. * phase - 1.,
+            Waveform::Triangle => 1. - 4. * (phase - 0.5).abs(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ToneParams {
+    pub waveform: Waveform,
+    pub amplifier: f32,
+}
+impl Default for ToneParams {
+    fn default() -> Self {
+        Self {
+            waveform: Waveform::default(),
+            amplifier: 1.,
+        }
+    }
+}
+
+/// A renderer generating a periodic tone, for beeps and test signals.
+pub struct Oscillator {
+    params: ToneParams,
+    frequency: f32,
+    phase: f32,
+    remaining: Option<f64>,
+}
+
+impl Oscillator {
+    /// Creates an oscillator at `frequency` Hz. With a `duration` (in seconds)
+    /// it dies once that much has been rendered, otherwise it plays forever.
+    pub fn new(frequency: f32, duration: Option<f32>, params: ToneParams) -> Self {
+        Self {
+            params,
+            frequency,
+            phase: 0.,
+            remaining: duration.map(f64::from),
+        }
+    }
+
+    #[inline]
+    fn next(&mut self, delta: f64) -> Option<f32> {
+        if let Some(remaining) = &mut self.remaining {
+            if *remaining <= 0. {
+                return None;
+            }
+            *remaining -= delta;
+        }
+        let value = self.params.waveform.sample(self.phase) * self.params.amplifier;
+        self.phase = (self.phase + self.frequency * delta as f32).fract();
+        Some(value)
+    }
+}
+
+impl Renderer for Oscillator {
+    fn alive(&self) -> bool {
+        !matches!(self.remaining, Some(remaining) if remaining <= 0.)
+    }
+
+    fn name(&self) -> &'static str {
+        "Oscillator"
+    }
+
+    fn render(&mut self, sample_rate: u32, channels: u16, data: &mut [f32]) {
+        let delta = 1. / sample_rate as f64;
+        mix_frames(channels, data, || {
+            self.next(delta).map(|value| Frame(value, value))
+        });
+    }
+}
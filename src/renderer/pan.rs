@@ -0,0 +1,96 @@
+use crate::{Frame, SasaError};
+use anyhow::{bail, Result};
+use std::f32::consts::FRAC_PI_4;
+
+/// How the level of each channel follows the pan position.
+///
+/// This works as a balance control: a centred sound plays unchanged, the side
+/// it's panned towards stays at unity and only the far side is turned down, so
+/// panning never boosts a channel into clipping. The laws are named after the
+/// centre dip of their usual form, and here only differ in how fast the far
+/// side falls off, reaching silence at a hard pan either way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PanLaw {
+    /// Equal-power curve, the far side at about -5.3dB halfway out.
+    #[default]
+    EqualPower,
+    /// Straight line, the far side at -6dB halfway out.
+    Linear,
+    /// Between the two, the far side at about -5.7dB halfway out.
+    Compromise,
+}
+
+impl PanLaw {
+    fn raw(self, pan: f32) -> (f32, f32) {
+        let linear = ((1. - pan) * 0.5, (1. + pan) * 0.5);
+        let angle = (pan + 1.) * FRAC_PI_4;
+        let power = (angle.cos(), angle.sin());
+        match self {
+            PanLaw::EqualPower => power,
+            PanLaw::Linear => linear,
+            PanLaw::Compromise => ((linear.0 * power.0).sqrt(), (linear.1 * power.1).sqrt()),
+        }
+    }
+
+    /// Left and right gains for `pan`, from -1 (left) to 1 (right), both
+    /// within [0, 1].
+    pub fn gains(self, pan: f32) -> (f32, f32) {
+        let pan = pan.clamp(-1., 1.);
+        if pan == 0. {
+            return (1., 1.);
+        }
+        let center = self.raw(0.).0;
+        let (left, right) = self.raw(pan);
+        (
+            (left / center).clamp(0., 1.),
+            (right / center).clamp(0., 1.),
+        )
+    }
+}
+
+/// Rejects pans outside [-1, 1], NaN included, which would otherwise poison
+/// the whole mix.
+pub(crate) fn validate_pan(pan: f32) -> Result<()> {
+    if !(-1. ..=1.).contains(&pan) {
+        bail!(SasaError::InvalidParameter(format!(
+            "pan must be within [-1, 1], got {pan}"
+        )));
+    }
+    Ok(())
+}
+
+#[inline(always)]
+pub(crate) fn apply_pan(frame: Frame, (left, right): (f32, f32)) -> Frame {
+    Frame(frame.0 * left, frame.1 * right)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LAWS: [PanLaw; 3] = [PanLaw::EqualPower, PanLaw::Linear, PanLaw::Compromise];
+
+    #[test]
+    fn panning_never_boosts_a_side() {
+        for law in LAWS {
+            assert_eq!(law.gains(0.), (1., 1.));
+            for i in 1..=20 {
+                let pan = i as f32 / 20.;
+                let (far, near) = law.gains(pan);
+                assert_eq!(near, 1.);
+                assert!((0. ..1.).contains(&far));
+                let (left, right) = law.gains(-pan);
+                assert!(left == 1. && (right - far).abs() < 1e-6);
+            }
+            assert!(law.gains(1.).0.abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn far_side_follows_the_law() {
+        let db = |law: PanLaw| 20. * law.gains(0.5).0.log10();
+        assert!((db(PanLaw::EqualPower) + 5.33).abs() < 0.01);
+        assert!((db(PanLaw::Linear) + 6.02).abs() < 0.01);
+        assert!((db(PanLaw::Compromise) + 5.68).abs() < 0.01);
+    }
+}
@@ -0,0 +1,153 @@
+use crate::Frame;
+use std::f32::consts::TAU;
+
+/// An "old radio" coloring, see [`crate::Music::set_radio_effect`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RadioParams {
+    /// Frequencies below this many Hz are cut.
+    pub low_cut: f32,
+    /// Frequencies above this many Hz are cut.
+    pub high_cut: f32,
+    /// Amount of soft saturation, squashing peaks above roughly `1 / drive`.
+    /// Zero leaves the signal clean.
+    pub drive: f32,
+    /// Quantizes the output to this many bits, for a crunchier sound.
+    pub bit_depth: Option<u8>,
+}
+impl Default for RadioParams {
+    fn default() -> Self {
+        Self {
+            low_cut: 400.,
+            high_cut: 3500.,
+            drive: 2.,
+            bit_depth: None,
+        }
+    }
+}
+
+/// Two-pole band-pass (two one-poles on either side) into a waveshaper.
+pub(crate) struct Radio {
+    params: RadioParams,
+    low_coeff: f32,
+    high_coeff: f32,
+    /// Low-passes at `low_cut`, whose output is subtracted to high-pass
+    low_state: [Frame; 2],
+    /// Low-passes at `high_cut`
+    high_state: [Frame; 2],
+}
+
+impl Radio {
+    pub fn new(params: RadioParams, sample_rate: u32) -> Self {
+        let mut res = Self {
+            params,
+            low_coeff: 0.,
+            high_coeff: 0.,
+            low_state: [Frame::default(); 2],
+            high_state: [Frame::default(); 2],
+        };
+        res.set_sample_rate(sample_rate);
+        res
+    }
+
+    /// Takes new parameters, keeping the filter state so that this doesn't click.
+    pub fn set_params(&mut self, params: RadioParams, sample_rate: u32) {
+        self.params = params;
+        self.set_sample_rate(sample_rate);
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        let coeff = |cutoff: f32| (-TAU * cutoff.max(0.) / sample_rate as f32).exp();
+        self.low_coeff = coeff(self.params.low_cut);
+        self.high_coeff = coeff(self.params.high_cut);
+    }
+
+    pub fn reset(&mut self) {
+        self.low_state = [Frame::default(); 2];
+        self.high_state = [Frame::default(); 2];
+    }
+
+    #[inline]
+    fn one_pole(state: &mut [Frame; 2], coeff: f32, input: Frame) -> Frame {
+        let mut frame = input;
+        for state in state {
+            *state = (frame * (1. - coeff) + *state * coeff).flush_denormals();
+            frame = *state;
+        }
+        frame
+    }
+
+    #[inline]
+    pub fn process(&mut self, frame: Frame) -> Frame {
+        let frame = frame - Self::one_pole(&mut self.low_state, self.low_coeff, frame);
+        let frame = Self::one_pole(&mut self.high_state, self.high_coeff, frame);
+        let drive = self.params.drive;
+        let steps = self
+            .params
+            .bit_depth
+            .map(|bits| (1u32 << (bits.clamp(1, 24) - 1)) as f32);
+        let shape = |mut value: f32| {
+            if drive > 0. {
+                // Unity gain for quiet signals, peaks get squashed
+                value = (value * drive).tanh() / drive;
+            }
+            if let Some(steps) = steps {
+                value = (value * steps).round() / steps;
+            }
+            value
+        };
+        Frame(shape(frame.0), shape(frame.1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    /// Peak of the output for a sine of `frequency`, once the filters settled.
+    fn peak(params: RadioParams, frequency: f32) -> f32 {
+        let mut radio = Radio::new(params, SAMPLE_RATE);
+        (0..SAMPLE_RATE / 2)
+            .map(|i| {
+                let value = (TAU * frequency * i as f32 / SAMPLE_RATE as f32).sin();
+                radio.process(Frame(value, value)).0
+            })
+            .skip(SAMPLE_RATE as usize / 4)
+            .fold(0., |peak, it| peak.max(it.abs()))
+    }
+
+    #[test]
+    fn band_outside_the_cuts_is_attenuated() {
+        let clean = RadioParams {
+            drive: 0.,
+            ..RadioParams::default()
+        };
+        let pass = peak(clean, 1200.);
+        assert!(pass > 0.6, "{pass}");
+        // The high-pass only rolls off at first order
+        assert!(peak(clean, 50.) < pass * 0.3);
+        assert!(peak(clean, 15000.) < pass * 0.1);
+    }
+
+    #[test]
+    fn drive_and_bit_depth_shape_the_output() {
+        let driven = RadioParams {
+            drive: 4.,
+            ..RadioParams::default()
+        };
+        assert!(peak(driven, 1200.) < 0.25);
+
+        let crushed = RadioParams {
+            drive: 0.,
+            bit_depth: Some(3),
+            ..RadioParams::default()
+        };
+        let mut radio = Radio::new(crushed, SAMPLE_RATE);
+        for i in 0..1000 {
+            let value = (TAU * 1200. * i as f32 / SAMPLE_RATE as f32).sin();
+            let out = radio.process(Frame(value, value)).0 * 4.;
+            assert_eq!(out, out.round());
+        }
+    }
+}
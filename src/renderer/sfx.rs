@@ -1,89 +1,399 @@
-use crate::{buffer_is_full, AudioClip, Renderer};
+use super::{
+    mix_frames,
+    pan::{apply_pan, validate_pan, PanLaw},
+};
+use crate::{buffer_is_full, AudioClip, Frame, Renderer, Resampler};
 use anyhow::{Context, Result};
 use ringbuf::{HeapConsumer, HeapProducer, HeapRb};
-use std::sync::{Arc, Weak};
+use std::sync::{
+    atomic::{AtomicU32, AtomicUsize, Ordering},
+    Arc, Weak,
+};
 
 #[derive(Debug, Clone)]
 pub struct PlaySfxParams {
+    /// Relative to the clip's [`AudioClip::default_gain`].
     pub amplifier: f32,
+    /// Interpolation used for this voice, for trading CPU for quality per sound.
+    pub resampler: Resampler,
+    /// From -1 (left) to 1 (right).
+    pub pan: f32,
+    pub pan_law: PanLaw,
+    /// Length in seconds of a linear fade-in at the start, hiding the click of
+    /// a clip that doesn't start at zero.
+    pub attack: f32,
+    /// Length in seconds of a linear fade-out at the end of the clip.
+    pub release: f32,
 }
 impl Default for PlaySfxParams {
     fn default() -> Self {
-        Self { amplifier: 1. }
+        Self {
+            amplifier: 1.,
+            resampler: Resampler::default(),
+            pan: 0.,
+            pan_law: PanLaw::default(),
+            attack: 0.,
+            release: 0.,
+        }
     }
 }
 
+impl PlaySfxParams {
+    /// Gain of the attack/release envelope at `position`, with all values in
+    /// frames of the clip.
+    #[inline]
+    fn envelope(&self, position: f64, length: f64, sample_rate: f64) -> f32 {
+        let mut gain = 1.;
+        if self.attack > 0. {
+            gain *= (position / (self.attack as f64 * sample_rate)).min(1.);
+        }
+        if self.release > 0. {
+            gain *= ((length - position) / (self.release as f64 * sample_rate)).clamp(0., 1.);
+        }
+        gain as f32
+    }
+}
+
+struct Voice {
+    /// Output frames left before the voice starts
+    delay: u32,
+    /// In frames of the clip
+    position: f64,
+    params: PlaySfxParams,
+    /// Value of [`Sfx::stop_all`]'s counter when the voice was played
+    generation: u32,
+    /// Output frames left of the fade-out after being stopped while playing
+    stopping: Option<u32>,
+}
+
+#[derive(Default)]
+struct SharedState {
+    active_voices: AtomicUsize,
+    /// Voices played before the last [`Sfx::stop_all`] have a lower generation
+    generation: AtomicU32,
+}
+
 pub(crate) struct SfxRenderer {
     clip: AudioClip,
-    arc: Weak<()>,
-    cons: HeapConsumer<(f32, PlaySfxParams)>,
+    state: Weak<SharedState>,
+    cons: HeapConsumer<Voice>,
+    /// Fade-out of stopped voices in seconds
+    stop_fade: f32,
+    /// Voices currently playing. Capped at the ring's capacity so that it never
+    /// reallocates on the audio thread; newer plays wait in the ring meanwhile.
+    voices: Vec<Voice>,
+}
+
+impl SfxRenderer {
+    fn update_state(&self) {
+        if let Some(state) = self.state.upgrade() {
+            state
+                .active_voices
+                .store(self.voices.len() + self.cons.len(), Ordering::SeqCst);
+        }
+    }
 }
 
 impl Renderer for SfxRenderer {
     fn alive(&self) -> bool {
-        !self.cons.is_empty() || self.arc.strong_count() != 0
+        !self.voices.is_empty() || !self.cons.is_empty() || self.state.strong_count() != 0
     }
 
-    fn render_mono(&mut self, sample_rate: u32, data: &mut [f32]) {
-        let delta = 1. / sample_rate as f32;
-        let mut pop_count = 0;
-        for (position, params) in self.cons.iter_mut() {
-            for sample in data.iter_mut() {
-                if let Some(frame) = self.clip.sample(*position) {
-                    *sample += frame.avg() * params.amplifier;
-                } else {
-                    pop_count += 1;
-                    break;
-                }
-                *position += delta;
-            }
-        }
-        unsafe {
-            self.cons.advance(pop_count);
-        }
+    fn name(&self) -> &'static str {
+        "Sfx"
     }
 
-    fn render_stereo(&mut self, sample_rate: u32, data: &mut [f32]) {
-        let delta = 1. / sample_rate as f32;
-        let mut pop_count = 0;
-        for (position, params) in self.cons.iter_mut() {
-            for sample in data.chunks_exact_mut(2) {
-                if let Some(frame) = self.clip.sample(*position) {
-                    sample[0] += frame.0 * params.amplifier;
-                    sample[1] += frame.1 * params.amplifier;
+    fn render(&mut self, sample_rate: u32, channels: u16, data: &mut [f32]) {
+        // Voices from before the last `stop_all` fade out if they are audible
+        // and are dropped otherwise, while anything played after it carries
+        // the new generation
+        let generation = self
+            .state
+            .upgrade()
+            .map_or(0, |it| it.generation.load(Ordering::SeqCst));
+        let stop_frames = (self.stop_fade * sample_rate as f32).round() as u32;
+        self.voices.retain_mut(|voice| {
+            if voice.generation >= generation || voice.stopping.is_some() {
+                return true;
+            }
+            if voice.delay != 0 || stop_frames == 0 {
+                return false;
+            }
+            voice.stopping = Some(stop_frames);
+            true
+        });
+        let free = self.voices.capacity() - self.voices.len();
+        self.voices.extend(
+            self.cons
+                .pop_iter()
+                .filter(|voice| voice.generation >= generation)
+                .take(free),
+        );
+
+        // Positions count clip frames, so a change of output rate between calls
+        // needs no rescaling. When the rates match, the step is exactly one and
+        // every frame is played once, however the clip falls across blocks.
+        let clip = &self.clip;
+        let delta = clip.sample_rate() as f64 / sample_rate as f64;
+        // Finished voices can be anywhere in the list, so drop them individually
+        self.voices.retain_mut(|voice| {
+            let Voice {
+                delay,
+                position,
+                params,
+                stopping,
+                ..
+            } = voice;
+            let amp = params.amplifier * clip.default_gain();
+            let gains = params.pan_law.gains(params.pan);
+            let enveloped = params.attack > 0. || params.release > 0.;
+            let length = clip.frame_count() as f64;
+            let clip_rate = clip.sample_rate() as f64;
+            let mut ended = false;
+            mix_frames(channels, data, || {
+                if *delay != 0 {
+                    *delay -= 1;
+                    return Some(Frame::default());
+                }
+                let Some(frame) = params.resampler.sample(clip.frames(), *position) else {
+                    ended = true;
+                    return None;
+                };
+                let mut amp = if enveloped {
+                    amp * params.envelope(*position, length, clip_rate)
                 } else {
-                    pop_count += 1;
-                    break;
+                    amp
+                };
+                if let Some(remaining) = stopping {
+                    if *remaining == 0 {
+                        ended = true;
+                        return None;
+                    }
+                    *remaining -= 1;
+                    // The output rate may have changed since the fade started
+                    amp *= (*remaining as f32 / stop_frames.max(1) as f32).min(1.);
                 }
                 *position += delta;
-            }
-        }
-        unsafe {
-            self.cons.advance(pop_count);
-        }
+                Some(apply_pan(frame * amp, gains))
+            });
+            !ended
+        });
+        self.update_state();
     }
 }
 
 pub struct Sfx {
-    _arc: Arc<()>,
-    prod: HeapProducer<(f32, PlaySfxParams)>,
+    arc: Arc<SharedState>,
+    prod: HeapProducer<Voice>,
+    /// Stamped on new voices, only ever changed by this handle
+    generation: u32,
 }
 impl Sfx {
-    pub(crate) fn new(clip: AudioClip, buffer_size: Option<usize>) -> (Sfx, SfxRenderer) {
-        let (prod, cons) = HeapRb::new(buffer_size.unwrap_or(64)).split();
-        let arc = Arc::new(());
+    pub(crate) fn new(
+        clip: AudioClip,
+        buffer_size: Option<usize>,
+        stop_fade: f32,
+    ) -> (Sfx, SfxRenderer) {
+        let buffer_size = buffer_size.unwrap_or(64);
+        let (prod, cons) = HeapRb::new(buffer_size).split();
+        let arc = Arc::default();
         let renderer = SfxRenderer {
             clip,
-            arc: Arc::downgrade(&arc),
+            state: Arc::downgrade(&arc),
             cons,
+            stop_fade,
+            voices: Vec::with_capacity(buffer_size),
         };
-        (Self { _arc: arc, prod }, renderer)
+        (
+            Self {
+                arc,
+                prod,
+                generation: 0,
+            },
+            renderer,
+        )
+    }
+
+    /// A renderer playing `clip` once and dying when done, with nothing left
+    /// to control it.
+    pub(crate) fn one_shot(clip: AudioClip, params: PlaySfxParams) -> SfxRenderer {
+        // Nothing is ever queued, and without a handle the renderer lives
+        // exactly as long as its voice
+        let (_, cons) = HeapRb::new(1).split();
+        SfxRenderer {
+            clip,
+            state: Weak::new(),
+            cons,
+            stop_fade: 0.,
+            voices: vec![Voice {
+                delay: 0,
+                position: 0.,
+                params,
+                generation: 0,
+                stopping: None,
+            }],
+        }
     }
 
     pub fn play(&mut self, params: PlaySfxParams) -> Result<()> {
-        self.prod
-            .push((0., params))
+        self.play_delayed(params, 0)
+    }
+
+    /// Plays the sound after `delay` output frames, e.g. for layering hits a
+    /// few milliseconds apart with sub-block precision.
+    pub fn play_delayed(&mut self, params: PlaySfxParams, delay: u32) -> Result<()> {
+        validate_pan(params.pan)?;
+        self.push_voice(params, delay)
             .map_err(buffer_is_full)
             .context("play sfx")
     }
+
+    /// Like [`Sfx::play`], but just returns `false` if the voice was dropped
+    /// because the buffer is full, which is usually fine for rapid-fire sounds.
+    /// An invalid pan drops the voice as well.
+    pub fn try_play(&mut self, params: PlaySfxParams) -> bool {
+        validate_pan(params.pan).is_ok() && self.push_voice(params, 0).is_ok()
+    }
+
+    /// Queues a new voice, handing it back if the buffer is full.
+    fn push_voice(&mut self, params: PlaySfxParams, delay: u32) -> Result<(), Voice> {
+        self.prod.push(Voice {
+            delay,
+            position: 0.,
+            params,
+            generation: self.generation,
+            stopping: None,
+        })
+    }
+
+    /// Stops every voice played so far, both playing and queued, with the next
+    /// audio callback. Playing ones fade out over
+    /// [`crate::AudioManagerBuilder::stop_fade`] rather than being cut off.
+    /// Voices played after this call are unaffected, even if they reach the
+    /// renderer in the same block.
+    pub fn stop_all(&mut self) {
+        self.generation += 1;
+        self.arc.generation.store(self.generation, Ordering::SeqCst);
+    }
+
+    /// Number of voices of this sound that were still playing (or queued) as
+    /// of the last audio callback.
+    pub fn active_voices(&self) -> usize {
+        self.arc.active_voices.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render_frames;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    fn play_and_stop(stop_fade: f32) -> (Sfx, Vec<f32>) {
+        let clip = AudioClip::from_raw(vec![Frame(0.5, 0.5); SAMPLE_RATE as usize], SAMPLE_RATE);
+        let (mut sfx, mut renderer) = Sfx::new(clip, None, stop_fade);
+        sfx.play(PlaySfxParams::default()).unwrap();
+        render_frames(&mut renderer, SAMPLE_RATE, 100);
+        sfx.stop_all();
+        (sfx, render_frames(&mut renderer, SAMPLE_RATE, 1000))
+    }
+
+    #[test]
+    fn stopped_voices_fade_out() {
+        // 240 frames
+        let (sfx, out) = play_and_stop(0.005);
+        let left: Vec<_> = out.iter().step_by(2).copied().collect();
+        assert!(left[0] > 0.3);
+        assert!(left.windows(2).all(|it| it[1] <= it[0]));
+        assert!(left[240..].iter().all(|&it| it == 0.));
+        assert_eq!(sfx.active_voices(), 0);
+    }
+
+    #[test]
+    fn invalid_pan_is_rejected() {
+        let clip = AudioClip::from_raw(vec![Frame(0.5, 0.5); 100], SAMPLE_RATE);
+        let (mut sfx, mut renderer) = Sfx::new(clip, None, 0.);
+        let params = PlaySfxParams {
+            pan: f32::NAN,
+            ..Default::default()
+        };
+        let err = sfx.play(params.clone()).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(crate::SasaError::InvalidParameter(_))
+        ));
+        assert!(!sfx.try_play(params));
+        assert!(render_frames(&mut renderer, SAMPLE_RATE, 10)
+            .iter()
+            .all(|&it| it == 0.));
+    }
+
+    #[test]
+    fn zero_stop_fade_cuts_right_away() {
+        let (sfx, out) = play_and_stop(0.);
+        assert!(out.iter().all(|&it| it == 0.));
+        assert_eq!(sfx.active_voices(), 0);
+    }
+
+    #[test]
+    fn output_rate_change_continues_from_the_same_position() {
+        // Each frame holds its own index, so the output tells the position
+        let ramp = (0..1000).map(|i| Frame(i as f32, i as f32)).collect();
+        let clip = AudioClip::from_raw(ramp, SAMPLE_RATE);
+        let (mut sfx, mut renderer) = Sfx::new(clip, None, 0.);
+        let params = PlaySfxParams {
+            resampler: Resampler::Linear,
+            ..PlaySfxParams::default()
+        };
+        let (gain, _) = params.pan_law.gains(params.pan);
+        sfx.play(params).unwrap();
+        let before = render_frames(&mut renderer, SAMPLE_RATE, 100);
+        assert_eq!(before[198] / gain, 99.);
+
+        // At half the rate, each output frame moves two frames on
+        let after = render_frames(&mut renderer, SAMPLE_RATE / 2, 100);
+        for (i, frame) in after.chunks_exact(2).enumerate() {
+            let position = frame[0] / gain;
+            assert!((position - (100 + 2 * i) as f32).abs() < 1e-3, "{position}");
+        }
+        // And back, one frame at a time from where it got to
+        let back = render_frames(&mut renderer, SAMPLE_RATE, 10);
+        assert!((back[0] / gain - 300.).abs() < 1e-3);
+        assert!((back[18] / gain - 309.).abs() < 1e-3);
+        assert_eq!(sfx.active_voices(), 1);
+    }
+
+    #[test]
+    fn short_clips_play_once_across_blocks() {
+        const BLOCK: usize = 64;
+        let clip = AudioClip::from_raw((1..=5).map(|i| Frame(i as f32, 0.)).collect(), SAMPLE_RATE);
+        let (mut sfx, mut renderer) = Sfx::new(clip, None, 0.);
+        let params = PlaySfxParams::default();
+        let (gain, _) = params.pan_law.gains(params.pan);
+        // One inside the first block, one straddling the boundary
+        sfx.play_delayed(params.clone(), 30).unwrap();
+        sfx.play_delayed(params, 62).unwrap();
+
+        let mut left: Vec<f32> = Vec::new();
+        for block in 0..2 {
+            let out = render_frames(&mut renderer, SAMPLE_RATE, BLOCK);
+            left.extend(out.iter().step_by(2).map(|it| it / gain));
+            // The first one ends within the first block and is dropped there
+            assert_eq!(renderer.voices.len(), 1 - block);
+        }
+        let mut expected = vec![0.; 2 * BLOCK];
+        for start in [30, 62] {
+            for i in 0..5 {
+                expected[start + i] = (i + 1) as f32;
+            }
+        }
+        for (i, (out, expected)) in left.iter().zip(&expected).enumerate() {
+            assert!(
+                (out - expected).abs() < 1e-5,
+                "frame {i}: {out} != {expected}"
+            );
+        }
+        assert_eq!(sfx.active_voices(), 0);
+    }
 }
@@ -0,0 +1,196 @@
+use super::{
+    mix_frames,
+    pan::{apply_pan, PanLaw},
+};
+use crate::{AudioClip, Frame, Renderer, Resampler};
+use std::sync::{
+    atomic::{AtomicU32, AtomicUsize, Ordering},
+    Arc, Weak,
+};
+
+#[derive(Debug, Clone)]
+pub struct SfxPoolParams {
+    /// Copies of the clip that may overlap. Triggers beyond that are dropped.
+    pub max_voices: usize,
+    /// Relative to the clip's [`AudioClip::default_gain`], shared by all voices.
+    pub amplifier: f32,
+    pub resampler: Resampler,
+    /// From -1 (left) to 1 (right).
+    pub pan: f32,
+    pub pan_law: PanLaw,
+}
+impl Default for SfxPoolParams {
+    fn default() -> Self {
+        Self {
+            max_voices: 64,
+            amplifier: 1.,
+            resampler: Resampler::default(),
+            pan: 0.,
+            pan_law: PanLaw::default(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct SharedState {
+    /// Triggers not yet picked up by the renderer
+    pending: AtomicU32,
+    active_voices: AtomicUsize,
+}
+
+pub(crate) struct SfxPoolRenderer {
+    clip: AudioClip,
+    params: SfxPoolParams,
+    state: Weak<SharedState>,
+    /// Play heads in frames of the clip. Negative ones are still waiting for
+    /// their staggered start.
+    voices: Vec<f64>,
+    /// Sum of all voices for one block, before gain and pan
+    scratch: Vec<Frame>,
+}
+
+impl Renderer for SfxPoolRenderer {
+    fn alive(&self) -> bool {
+        !self.voices.is_empty() || self.state.strong_count() != 0
+    }
+
+    fn name(&self) -> &'static str {
+        "SfxPool"
+    }
+
+    fn render(&mut self, sample_rate: u32, channels: u16, data: &mut [f32]) {
+        let frames = data.len() / channels as usize;
+        let delta = self.clip.sample_rate() as f64 / sample_rate as f64;
+        let state = self.state.upgrade();
+        let triggers = state
+            .as_ref()
+            .map_or(0, |it| it.pending.swap(0, Ordering::SeqCst)) as usize;
+        // Triggers since the last block are spread over this one, so that a
+        // burst doesn't stack up into a single loud, phasey hit
+        let started = triggers.min(self.params.max_voices - self.voices.len());
+        self.voices
+            .extend((0..started).map(|i| -((i * frames / started) as f64) * delta));
+
+        // Grows only until the block size settles
+        self.scratch.clear();
+        self.scratch.resize(frames, Frame::default());
+        let clip = self.clip.frames();
+        let resampler = self.params.resampler;
+        let scratch = &mut self.scratch;
+        self.voices.retain_mut(|position| {
+            for out in scratch.iter_mut() {
+                if *position >= 0. {
+                    let Some(frame) = resampler.sample(clip, *position) else {
+                        return false;
+                    };
+                    *out = *out + frame;
+                }
+                *position += delta;
+            }
+            true
+        });
+
+        // Gain and pan are shared, so they're applied once to the sum
+        let amp = self.params.amplifier * self.clip.default_gain();
+        let gains = self.params.pan_law.gains(self.params.pan);
+        let mut sum = self.scratch.iter();
+        mix_frames(channels, data, || {
+            sum.next().map(|&frame| apply_pan(frame * amp, gains))
+        });
+        if let Some(state) = state {
+            state
+                .active_voices
+                .store(self.voices.len(), Ordering::SeqCst);
+        }
+    }
+}
+
+/// Plays many overlapping copies of one clip, for sounds fired hundreds of
+/// times a second. Unlike [`crate::Sfx`], triggering only bumps a counter and
+/// all voices share their parameters, so the renderer sums them in one pass.
+pub struct SfxPool {
+    arc: Arc<SharedState>,
+}
+impl SfxPool {
+    pub(crate) fn new(clip: AudioClip, params: SfxPoolParams) -> (SfxPool, SfxPoolRenderer) {
+        let arc = Arc::default();
+        let renderer = SfxPoolRenderer {
+            clip,
+            state: Arc::downgrade(&arc),
+            voices: Vec::with_capacity(params.max_voices),
+            scratch: Vec::new(),
+            params,
+        };
+        (Self { arc }, renderer)
+    }
+
+    /// Starts another copy of the clip with the next audio callback. Never
+    /// fails: triggers beyond [`SfxPoolParams::max_voices`] are dropped.
+    pub fn trigger(&self) {
+        self.arc.pending.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Number of copies that were playing as of the last audio callback.
+    pub fn active_voices(&self) -> usize {
+        self.arc.active_voices.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render_frames;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    fn pool(frames: usize, max_voices: usize) -> (SfxPool, SfxPoolRenderer) {
+        let clip = AudioClip::from_raw(vec![Frame(1., 1.); frames], SAMPLE_RATE);
+        SfxPool::new(
+            clip,
+            SfxPoolParams {
+                max_voices,
+                ..SfxPoolParams::default()
+            },
+        )
+    }
+
+    #[test]
+    fn triggers_beyond_max_voices_are_dropped() {
+        let (pool, mut renderer) = pool(1000, 4);
+        for _ in 0..10 {
+            pool.trigger();
+        }
+        render_frames(&mut renderer, SAMPLE_RATE, 100);
+        assert_eq!(pool.active_voices(), 4);
+        // Still full, the new trigger is lost rather than queued
+        pool.trigger();
+        render_frames(&mut renderer, SAMPLE_RATE, 100);
+        assert_eq!(pool.active_voices(), 4);
+    }
+
+    #[test]
+    fn burst_is_spread_over_the_block() {
+        let (pool, mut renderer) = pool(1000, 64);
+        for _ in 0..4 {
+            pool.trigger();
+        }
+        let out = render_frames(&mut renderer, SAMPLE_RATE, 100);
+        let (gain, _) = PanLaw::default().gains(0.);
+        // A new voice joins every 25 frames
+        for (i, frame) in out.chunks_exact(2).enumerate() {
+            let voices = (i / 25 + 1) as f32;
+            assert!((frame[0] - voices * gain).abs() < 1e-5, "frame {i}");
+        }
+    }
+
+    #[test]
+    fn renderer_outlives_the_handle_until_voices_end() {
+        let (pool, mut renderer) = pool(150, 8);
+        pool.trigger();
+        render_frames(&mut renderer, SAMPLE_RATE, 100);
+        drop(pool);
+        assert!(renderer.alive());
+        render_frames(&mut renderer, SAMPLE_RATE, 100);
+        assert!(!renderer.alive());
+    }
+}
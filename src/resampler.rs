@@ -0,0 +1,181 @@
+use crate::Frame;
+use std::f64::consts::PI;
+
+/// Interpolation used when reading frames at a fractional position.
+///
+/// Cheaper variants suit mobile devices, while [`Resampler::Sinc`] trades
+/// CPU for quality on desktop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Resampler {
+    Nearest,
+    #[default]
+    Linear,
+    /// Catmull-Rom spline through the four surrounding frames.
+    Cubic,
+    /// Lanczos-windowed sinc using the given number of frames on each side.
+    Sinc(usize),
+}
+
+#[inline(always)]
+fn get(frames: &[Frame], index: isize) -> Frame {
+    frames[index.clamp(0, frames.len() as isize - 1) as usize]
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-9 {
+        1.
+    } else {
+        let x = x * PI;
+        x.sin() / x
+    }
+}
+
+/// Zeroth-order modified Bessel function of the first kind.
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.;
+    let mut term = 1.;
+    let half = x / 2.;
+    for k in 1..64 {
+        term *= half / k as f64;
+        let t = term * term;
+        sum += t;
+        if t < sum * 1e-16 {
+            break;
+        }
+    }
+    sum
+}
+
+/// Converts `frames` by `ratio` (source rate over target rate) with a
+/// Kaiser-windowed sinc of `taps` zero crossings on each side.
+///
+/// Unlike the real-time [`Resampler::Sinc`], the filter's cutoff follows the
+/// target rate when downsampling, so content above the new Nyquist frequency
+/// is removed instead of aliasing. That makes it much slower, and only suited
+/// for converting ahead of time.
+pub(crate) fn kaiser_resample(frames: &[Frame], ratio: f64, taps: usize) -> Vec<Frame> {
+    const BETA: f64 = 8.6;
+    let taps = taps.max(1) as f64;
+    // Cutoff relative to the source's Nyquist frequency
+    let cutoff = (1. / ratio).min(1.);
+    let half_width = taps / cutoff;
+    let norm = bessel_i0(BETA);
+    let len = (frames.len() as f64 / ratio).ceil() as usize;
+    (0..len)
+        .map(|i| {
+            let position = i as f64 * ratio;
+            let start = (position - half_width).ceil() as isize;
+            let end = (position + half_width).floor() as isize;
+            let mut sum = Frame::default();
+            let mut weight_sum = 0.;
+            for k in start..=end {
+                let x = position - k as f64;
+                let r = x / half_width;
+                let window = bessel_i0(BETA * (1. - r * r).max(0.).sqrt()) / norm;
+                let weight = sinc(x * cutoff) * window;
+                sum = sum + get(frames, k) * weight as f32;
+                weight_sum += weight;
+            }
+            sum * (1. / weight_sum) as f32
+        })
+        .collect()
+}
+
+impl Resampler {
+    /// Samples `frames` at `position`, measured in frames. Returns `None` once
+    /// `position` is past the last frame.
+    pub fn sample(self, frames: &[Frame], position: f64) -> Option<Frame> {
+        if !(0. ..frames.len() as f64).contains(&position) {
+            return None;
+        }
+        let index = position as usize;
+        let f = position - index as f64;
+        let index = index as isize;
+        Some(match self {
+            Resampler::Nearest => get(frames, position.round() as isize),
+            Resampler::Linear => get(frames, index).interpolate(&get(frames, index + 1), f as f32),
+            Resampler::Cubic => {
+                let p0 = get(frames, index - 1);
+                let p1 = get(frames, index);
+                let p2 = get(frames, index + 1);
+                let p3 = get(frames, index + 2);
+                let f = f as f32;
+                let cubic = |p0: f32, p1: f32, p2: f32, p3: f32| {
+                    p1 + 0.5
+                        * f
+                        * (p2 - p0
+                            + f * (2. * p0 - 5. * p1 + 4. * p2 - p3
+                                + f * (3. * (p1 - p2) + p3 - p0)))
+                };
+                Frame(cubic(p0.0, p1.0, p2.0, p3.0), cubic(p0.1, p1.1, p2.1, p3.1))
+            }
+            Resampler::Sinc(taps) => {
+                let taps = taps.max(1) as isize;
+                let mut sum = Frame::default();
+                let mut weight_sum = 0.;
+                for k in (index - taps + 1)..=(index + taps) {
+                    let x = position - k as f64;
+                    let weight = sinc(x) * sinc(x / taps as f64);
+                    sum = sum + get(frames, k) * weight as f32;
+                    weight_sum += weight;
+                }
+                sum * (1. / weight_sum) as f32
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// RMS of the left channel after resampling a one second sine from 48 kHz
+    /// to 22.05 kHz, ignoring the edges where the filter runs out of input.
+    fn resampled_rms(frequency: f64) -> f32 {
+        let frames: Vec<Frame> = (0..48000)
+            .map(|i| {
+                let value = (i as f64 / 48000. * frequency * std::f64::consts::TAU).sin() as f32;
+                Frame(value, value)
+            })
+            .collect();
+        let out = kaiser_resample(&frames, 48000. / 22050., 16);
+        let body = &out[1000..out.len() - 1000];
+        (body.iter().map(|it| it.0 * it.0).sum::<f32>() / body.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn sinc_downsampling_removes_content_above_nyquist() {
+        // Full-scale sine RMS is about 0.707
+        assert!(resampled_rms(1000.) > 0.69);
+        // 14 kHz is above the new 11.025 kHz Nyquist and would alias to 8.05 kHz
+        assert!(resampled_rms(14000.) < 0.01);
+    }
+
+    #[test]
+    fn sinc_downsampling_aliases_far_less_than_linear() {
+        // One second sweeping from 100 Hz to 20 kHz at 48 kHz
+        let (start, end) = (100., 20000.);
+        let sweep: Vec<Frame> = (0..48000)
+            .map(|i| {
+                let t = i as f64 / 48000.;
+                let phase = std::f64::consts::TAU * (start * t + (end - start) * t * t / 2.);
+                let value = phase.sin() as f32;
+                Frame(value, value)
+            })
+            .collect();
+        let ratio = 48000. / 22050.;
+        let sinc = kaiser_resample(&sweep, ratio, 16);
+        let linear: Vec<Frame> = (0..sinc.len())
+            .map_while(|i| Resampler::Linear.sample(&sweep, i as f64 * ratio))
+            .collect();
+        // From 0.65 s on the sweep is above 13 kHz, past the new 11.025 kHz
+        // Nyquist and the filter's transition, so anything left is aliasing
+        let aliasing = |out: &[Frame]| {
+            let tail = &out[(0.65 * 22050.) as usize..(0.98 * 22050.) as usize];
+            (tail.iter().map(|it| it.0 * it.0).sum::<f32>() / tail.len() as f32).sqrt()
+        };
+        let (sinc, linear) = (aliasing(&sinc), aliasing(&linear));
+        assert!(linear > 0.1, "{linear}");
+        assert!(sinc < linear * 0.02, "sinc {sinc}, linear {linear}");
+    }
+}
@@ -0,0 +1,142 @@
+use crate::SasaError;
+use anyhow::{bail, Result};
+
+/// Piecewise-constant tempo over the mixer's clock, converting between beats
+/// and frames of [`crate::AudioManager::frames_rendered`].
+#[derive(Debug, Clone)]
+pub struct TempoMap {
+    sample_rate: u32,
+    /// `(sample, bpm, beat)`, the beat being the one reached at `sample`
+    points: Vec<(u64, f64, f64)>,
+}
+
+impl TempoMap {
+    /// Each `(sample, bpm)` point sets the tempo from that frame on, the first
+    /// one placing beat zero. The map is extrapolated with the first tempo
+    /// before it, giving negative beats.
+    ///
+    /// `sample_rate` is the rate the samples are counted at, normally
+    /// [`crate::AudioManager::sample_rate`].
+    pub fn new(sample_rate: u32, points: Vec<(u64, f64)>) -> Result<Self> {
+        if sample_rate == 0 {
+            bail!(SasaError::InvalidParameter(
+                "sample rate must be positive".to_owned()
+            ));
+        }
+        if points.is_empty() {
+            bail!(SasaError::InvalidParameter(
+                "tempo map needs at least one point".to_owned()
+            ));
+        }
+        let mut beat = 0.;
+        let mut result: Vec<(u64, f64, f64)> = Vec::with_capacity(points.len());
+        for (sample, bpm) in points {
+            if !(bpm.is_finite() && bpm > 0.) {
+                bail!(SasaError::InvalidParameter(format!(
+                    "invalid tempo {bpm} at sample {sample}"
+                )));
+            }
+            if let Some(&(last, last_bpm, last_beat)) = result.last() {
+                if sample <= last {
+                    bail!(SasaError::InvalidParameter(format!(
+                        "tempo map points must be strictly increasing, got {sample} after {last}"
+                    )));
+                }
+                beat = last_beat + (sample - last) as f64 * last_bpm / (60. * sample_rate as f64);
+            }
+            result.push((sample, bpm, beat));
+        }
+        Ok(Self {
+            sample_rate,
+            points: result,
+        })
+    }
+
+    /// A map with a single tempo, beat zero falling on `start`.
+    pub fn constant(sample_rate: u32, start: u64, bpm: f64) -> Result<Self> {
+        Self::new(sample_rate, vec![(start, bpm)])
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Tempo in effect at `sample`.
+    pub fn bpm_at(&self, sample: u64) -> f64 {
+        self.segment_by_sample(sample as f64).1
+    }
+
+    /// Frame at which `beat` falls, fractional between frames.
+    pub fn beat_to_sample(&self, beat: f64) -> f64 {
+        let index = self
+            .points
+            .partition_point(|&(_, _, start)| start <= beat)
+            .saturating_sub(1);
+        let (sample, bpm, start) = self.points[index];
+        sample as f64 + (beat - start) * 60. * self.sample_rate as f64 / bpm
+    }
+
+    /// Beat reached at `sample`, fractional between beats.
+    pub fn sample_to_beat(&self, sample: f64) -> f64 {
+        let (start, bpm, beat) = self.segment_by_sample(sample);
+        beat + (sample - start as f64) * bpm / (60. * self.sample_rate as f64)
+    }
+
+    fn segment_by_sample(&self, sample: f64) -> (u64, f64, f64) {
+        let index = self
+            .points
+            .partition_point(|&(start, _, _)| start as f64 <= sample)
+            .saturating_sub(1);
+        self.points[index]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_invalid(result: Result<TempoMap>) -> bool {
+        matches!(
+            result.unwrap_err().downcast_ref(),
+            Some(SasaError::InvalidParameter(_))
+        )
+    }
+
+    #[test]
+    fn invalid_points_are_rejected() {
+        assert!(is_invalid(TempoMap::new(48000, Vec::new())));
+        assert!(is_invalid(TempoMap::new(0, vec![(0, 120.)])));
+        assert!(is_invalid(TempoMap::new(
+            48000,
+            vec![(100, 120.), (50, 90.)]
+        )));
+        assert!(is_invalid(TempoMap::new(
+            48000,
+            vec![(100, 120.), (100, 90.)]
+        )));
+        for bpm in [0., -120., f64::NAN, f64::INFINITY] {
+            assert!(is_invalid(TempoMap::new(48000, vec![(0, bpm)])));
+        }
+    }
+
+    #[test]
+    fn beats_and_samples_round_trip() {
+        // Two beats at 120 BPM, then 60 BPM
+        let map = TempoMap::new(48000, vec![(1000, 120.), (49000, 60.)]).unwrap();
+        assert_eq!(map.sample_to_beat(1000.), 0.);
+        assert_eq!(map.sample_to_beat(49000.), 2.);
+        assert_eq!(map.beat_to_sample(3.), 97000.);
+        assert_eq!(map.bpm_at(48999), 120.);
+        assert_eq!(map.bpm_at(49000), 60.);
+        // Extrapolated with the first tempo before it
+        assert_eq!(map.sample_to_beat(0.), -1000. / 24000.);
+        for beat in [-1.5, 0., 0.25, 1.999, 2., 2.5, 10.] {
+            let sample = map.beat_to_sample(beat);
+            assert!((map.sample_to_beat(sample) - beat).abs() < 1e-9, "{beat}");
+        }
+        for sample in [0., 500.5, 1000., 48999., 49000., 123456.] {
+            let beat = map.sample_to_beat(sample);
+            assert!((map.beat_to_sample(beat) - sample).abs() < 1e-6, "{sample}");
+        }
+    }
+}
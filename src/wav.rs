@@ -0,0 +1,165 @@
+use crate::{renderer::Rng, Frame, SasaError};
+use anyhow::{bail, Result};
+use std::io::Write;
+
+/// Sample encoding of an exported WAV file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WavFormat {
+    /// 16-bit PCM with TPDF dither.
+    #[default]
+    Pcm16,
+    /// 24-bit PCM with TPDF dither.
+    Pcm24,
+    /// 32-bit float, written as is.
+    Float32,
+}
+
+impl WavFormat {
+    fn bits(self) -> u16 {
+        match self {
+            WavFormat::Pcm16 => 16,
+            WavFormat::Pcm24 => 24,
+            WavFormat::Float32 => 32,
+        }
+    }
+}
+
+/// Quantizes `sample` to a signed integer of `bits` bits, adding triangular
+/// dither of one LSB.
+#[inline]
+fn quantize(sample: f32, bits: u16, rng: &mut Rng) -> i32 {
+    let max = ((1i32 << (bits - 1)) - 1) as f32;
+    let dither = (rng.next_f32() + rng.next_f32()) * 0.5;
+    (sample * max + dither).round().clamp(-max - 1., max) as i32
+}
+
+/// Lengths of the `data` chunk and of the whole RIFF payload in bytes, failing
+/// when they don't fit the 32-bit size fields.
+fn chunk_lengths(frames: usize, block_align: u16, header_len: u32) -> Result<(u32, u32)> {
+    let lengths = (frames as u64)
+        .checked_mul(block_align as u64)
+        .and_then(|data| {
+            let riff = data.checked_add(header_len as u64)?;
+            Some((u32::try_from(data).ok()?, u32::try_from(riff).ok()?))
+        });
+    let Some(lengths) = lengths else {
+        bail!(SasaError::InvalidParameter(format!(
+            "{frames} frames are too long for a WAV file"
+        )));
+    };
+    Ok(lengths)
+}
+
+pub(crate) fn write(
+    mut w: impl Write,
+    frames: &[Frame],
+    sample_rate: u32,
+    format: WavFormat,
+) -> Result<()> {
+    const CHANNELS: u16 = 2;
+    let float = format == WavFormat::Float32;
+    let bits = format.bits();
+    let block_align = CHANNELS * bits / 8;
+    // Non-PCM files carry a fact chunk, and the float fmt chunk an empty extension
+    let fmt_len: u32 = if float { 18 } else { 16 };
+    let fact_len: u32 = if float { 12 } else { 0 };
+    let (data_len, riff_len) =
+        chunk_lengths(frames.len(), block_align, 4 + 8 + fmt_len + fact_len + 8)?;
+    let Some(byte_rate) = sample_rate.checked_mul(block_align as u32) else {
+        bail!(SasaError::InvalidParameter(format!(
+            "sample rate {sample_rate} is too high for a WAV file"
+        )));
+    };
+
+    w.write_all(b"RIFF")?;
+    w.write_all(&riff_len.to_le_bytes())?;
+    w.write_all(b"WAVE")?;
+
+    w.write_all(b"fmt ")?;
+    w.write_all(&fmt_len.to_le_bytes())?;
+    w.write_all(&(if float { 3u16 } else { 1 }).to_le_bytes())?;
+    w.write_all(&CHANNELS.to_le_bytes())?;
+    w.write_all(&sample_rate.to_le_bytes())?;
+    w.write_all(&byte_rate.to_le_bytes())?;
+    w.write_all(&block_align.to_le_bytes())?;
+    w.write_all(&bits.to_le_bytes())?;
+    if float {
+        w.write_all(&0u16.to_le_bytes())?;
+        w.write_all(b"fact")?;
+        w.write_all(&4u32.to_le_bytes())?;
+        w.write_all(&(frames.len() as u32).to_le_bytes())?;
+    }
+
+    w.write_all(b"data")?;
+    w.write_all(&data_len.to_le_bytes())?;
+    let mut rng = Rng::new(0);
+    for frame in frames {
+        for sample in [frame.0, frame.1] {
+            match format {
+                WavFormat::Pcm16 => {
+                    w.write_all(&(quantize(sample, 16, &mut rng) as i16).to_le_bytes())?
+                }
+                WavFormat::Pcm24 => {
+                    w.write_all(&quantize(sample, 24, &mut rng).to_le_bytes()[..3])?
+                }
+                WavFormat::Float32 => w.write_all(&sample.to_le_bytes())?,
+            }
+        }
+    }
+    w.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AudioClip;
+
+    #[test]
+    fn written_files_decode_to_the_same_frames() {
+        let frames: Vec<_> = (0..1000)
+            .map(|i| {
+                Frame(
+                    (i as f32 * 0.05).sin() * 0.9,
+                    (i as f32 * 0.03).cos() * -0.5,
+                )
+            })
+            .collect();
+        for (format, step) in [
+            (WavFormat::Pcm16, 1. / 32767.),
+            (WavFormat::Pcm24, 1. / 8388607.),
+            (WavFormat::Float32, 0.),
+        ] {
+            let mut data = Vec::new();
+            write(&mut data, &frames, 44100, format).unwrap();
+            let clip = AudioClip::new(data).unwrap();
+            assert_eq!(clip.sample_rate(), 44100);
+            assert_eq!(clip.frames().len(), frames.len());
+            // Rounding and dither are off by up to 1.5 steps, and full scale
+            // is written as 2^(bits-1) - 1 but read back as 2^(bits-1)
+            for (decoded, frame) in clip.frames().iter().zip(&frames) {
+                assert!(
+                    (decoded.0 - frame.0).abs() <= 3. * step
+                        && (decoded.1 - frame.1).abs() <= 3. * step,
+                    "{format:?}: {:?} vs {:?}",
+                    (decoded.0, decoded.1),
+                    (frame.0, frame.1)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn lengths_past_4_gib_are_rejected() {
+        assert_eq!(chunk_lengths(1000, 4, 36).unwrap(), (4000, 4036));
+        // The data chunk still fits, but not the header on top of it
+        let frames = (u32::MAX / 4) as usize;
+        assert!(chunk_lengths(frames, 4, 36).is_err());
+        assert!(chunk_lengths(1 << 30, 4, 36).is_err());
+        let err = chunk_lengths(1 << 31, 8, 36).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(SasaError::InvalidParameter(_))
+        ));
+    }
+}